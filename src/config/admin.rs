@@ -0,0 +1,122 @@
+// Copyright (c) 2018 libdeadmock developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! `libdeadmock` admin API configuration
+use crate::error::Error::{self, InvalidRuntimeConfig};
+use clap::ArgMatches;
+use getset::{Getters, Setters};
+use serde_derive::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// The default path the admin API is served under.
+const DEFAULT_ADMIN_PATH: &str = "/__admin";
+
+/// The admin settings for deadmock, i.e. whether an admin API is on, and the path it is
+/// served under.
+///
+/// # Example
+///
+/// ```
+/// # use libdeadmock::config;
+/// #
+/// # fn main() {
+///     // The admin API is disabled by default.
+///     let disabled = config::Admin::default();
+///     assert!(!disabled.enabled());
+///     assert_eq!(disabled.path(), "/__admin");
+/// # }
+/// ```
+#[derive(Clone, Debug, Deserialize, Getters, Hash, Eq, PartialEq, Serialize, Setters)]
+#[serde(default)]
+pub struct Admin {
+    /// Turn the admin API on.
+    #[get = "pub"]
+    #[set = "pub"]
+    enabled: bool,
+    /// The path the admin API is served under.  This must start with a `/`.
+    #[get = "pub"]
+    #[set = "pub"]
+    path: String,
+}
+
+impl Default for Admin {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            path: DEFAULT_ADMIN_PATH.to_string(),
+        }
+    }
+}
+
+impl Admin {
+    /// Check that the admin path is usable, i.e. it starts with a `/`.
+    crate fn check(&self) -> Result<(), Error> {
+        if self.path.starts_with('/') && self.path.len() > 1 {
+            Ok(())
+        } else {
+            Err(InvalidRuntimeConfig)
+        }
+    }
+
+    /// Override the settings whose command line arguments are given, keeping the rest.
+    crate fn with_matches<'a>(mut self, matches: &'a ArgMatches<'a>) -> Result<Self, Error> {
+        if matches.is_present("admin") {
+            self.enabled = true;
+        }
+        if let Some(path) = matches.value_of("admin-path") {
+            self.path = path.to_string();
+        }
+        self.check()?;
+        Ok(self)
+    }
+}
+
+impl<'a> TryFrom<&'a ArgMatches<'a>> for Admin {
+    type Error = Error;
+
+    fn try_from(matches: &'a ArgMatches<'a>) -> Result<Self, Error> {
+        Self::default().with_matches(matches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Admin;
+    use clap::{App, Arg};
+    use std::convert::TryFrom;
+
+    fn test_cli() -> App<'static, 'static> {
+        App::new("admin-config-test")
+            .version("1")
+            .author("Yoda")
+            .about("command line for admin config testing")
+            .arg(Arg::with_name("admin").long("admin"))
+            .arg(
+                Arg::with_name("admin-path")
+                    .long("admin-path")
+                    .takes_value(true)
+                    .value_name("PATH"),
+            )
+    }
+
+    #[test]
+    fn from_args() {
+        let arg_vec = vec!["test-cli", "--admin", "--admin-path", "/_mock"];
+        let matches = test_cli().get_matches_from(arg_vec);
+        match Admin::try_from(&matches) {
+            Ok(admin) => {
+                assert!(admin.enabled());
+                assert_eq!(admin.path(), "/_mock");
+            }
+            Err(e) => assert!(false, e.to_string()),
+        }
+
+        let matches = test_cli().get_matches_from(vec!["test-cli", "--admin-path", "_mock"]);
+        assert!(Admin::try_from(&matches).is_err());
+    }
+}
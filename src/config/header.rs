@@ -7,6 +7,7 @@
 // modified, or distributed except according to those terms.
 
 //! HTTP header configuration
+use crate::config::Pattern;
 use getset::{Getters, MutGetters, Setters};
 use libeither::Either;
 use serde_derive::{Deserialize, Serialize};
@@ -27,6 +28,20 @@ pub struct Header {
     value: String,
 }
 
+impl Header {
+    /// Create a new header configuration from the given key and value.
+    pub fn new<K, V>(key: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        Self {
+            key: key.into(),
+            value: value.into(),
+        }
+    }
+}
+
 impl fmt::Display for Header {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}: {}", self.key, self.value)
@@ -41,11 +56,11 @@ pub struct HeaderPattern {
     /// Either the header key, i.e. 'Content-Type' or a header key pattern, i.e. '^X-.*'
     #[get = "pub"]
     #[get_mut]
-    key: Either<String, String>,
+    key: Either<String, Pattern>,
     /// Either the header value, i.e. 'application/json' or a header key pattern, i.e. '^application/.*'
     #[get = "pub"]
     #[get_mut]
-    value: Either<String, String>,
+    value: Either<String, Pattern>,
 }
 
 impl fmt::Display for HeaderPattern {
@@ -57,6 +72,7 @@ impl fmt::Display for HeaderPattern {
 #[cfg(test)]
 crate mod test {
     use super::{Header, HeaderPattern};
+    use crate::config::pattern::test::{pattern, unchecked};
     use libeither::Either;
 
     const EMPTY_HEADER: &str = r#"{"key":"","value":""}"#;
@@ -76,7 +92,7 @@ right = "^application/.*"
     crate fn content_type_header_pattern() -> HeaderPattern {
         HeaderPattern {
             key: Either::new_left("Content-Type".to_string()),
-            value: Either::new_right("^application/.*".to_string()),
+            value: Either::new_right(pattern("^application/.*")),
         }
     }
 
@@ -90,14 +106,14 @@ right = "^application/.*"
     crate fn content_type_star_pattern() -> HeaderPattern {
         HeaderPattern {
             key: Either::new_left("Content-Type".to_string()),
-            value: Either::new_right("*".to_string()),
+            value: Either::new_right(unchecked("*")),
         }
     }
 
     crate fn accept_star_pattern() -> HeaderPattern {
         HeaderPattern {
             key: Either::new_left("Accept".to_string()),
-            value: Either::new_right("*".to_string()),
+            value: Either::new_right(unchecked("*")),
         }
     }
 
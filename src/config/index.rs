@@ -0,0 +1,223 @@
+// Copyright (c) 2018 libdeadmock developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! A lookup index over the mappings, so a request is only checked against the mappings that
+//! could match it.
+use crate::config::Mapping;
+use regex::RegexSet;
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Mapping ids by exact url and method, plus a regex set over the url patterns.
+#[derive(Clone, Debug, Default)]
+crate struct Index {
+    /// The ids of the mappings with an exact url, by url, then by method (`None` for any method).
+    exact: HashMap<String, HashMap<Option<String>, Vec<Uuid>>>,
+    /// The ids of the mappings with a url pattern, but no exact url.
+    pattern_ids: Vec<Uuid>,
+    /// The url patterns of `pattern_ids`, in the same order.
+    url_patterns: Vec<String>,
+    /// A regex set over the first `indexed_patterns` url patterns.  This is `None` if any of
+    /// those patterns is invalid, in which case every pattern mapping is a candidate.
+    patterns: Option<RegexSet>,
+    /// The number of url patterns in `patterns`.  The pattern mappings inserted since the set was
+    /// built are always candidates.
+    indexed_patterns: usize,
+    /// The ids of the mappings with no url criteria, or with url options.  These are always
+    /// candidates.
+    unindexed: Vec<Uuid>,
+}
+
+impl Index {
+    /// Index the given mappings.
+    crate fn new(mappings: &HashMap<Uuid, Arc<Mapping>>) -> Self {
+        let mut index = Self::default();
+        for (id, mapping) in mappings {
+            index.add(*id, mapping);
+        }
+        index.build_patterns();
+        index
+    }
+
+    /// Index one more mapping.
+    ///
+    /// The regex set is only rebuilt once the url patterns added since it was built outnumber
+    /// those in it, so inserting mappings one at a time stays linear overall.
+    crate fn insert(&mut self, id: Uuid, mapping: &Mapping) {
+        self.add(id, mapping);
+        if self.url_patterns.len() - self.indexed_patterns > self.indexed_patterns {
+            self.build_patterns();
+        }
+    }
+
+    fn add(&mut self, id: Uuid, mapping: &Mapping) {
+        let request = mapping.request();
+        if request.url_options().is_some() {
+            // The url options change how the path compares, so these are always checked.
+            self.unindexed.push(id);
+        } else if let Some(url) = request.url() {
+            // A url with a query string is indexed by its path.
+            let path = url.split('?').next().unwrap_or(url);
+            self.exact
+                .entry(path.to_string())
+                .or_insert_with(HashMap::new)
+                .entry(request.method().clone())
+                .or_insert_with(Vec::new)
+                .push(id);
+        } else if let Some(url_pattern) = request.url_pattern() {
+            self.pattern_ids.push(id);
+            self.url_patterns.push(url_pattern.as_str().to_string());
+        } else {
+            self.unindexed.push(id);
+        }
+    }
+
+    fn build_patterns(&mut self) {
+        self.patterns = RegexSet::new(&self.url_patterns).ok();
+        self.indexed_patterns = self.url_patterns.len();
+    }
+
+    /// The ids of the mappings that could match a request with the given method and path.
+    ///
+    /// `None` skips the lookup on that part of the request, i.e. because the matcher doesn't
+    /// check it, and returns every mapping that the lookup would have narrowed down.
+    ///
+    /// * `method` narrows the mappings with an exact url to those with the same method, or none.
+    /// * `path` narrows the mappings with an exact url to those with the same url.
+    /// * `pattern_path` narrows the mappings with a url pattern to those whose pattern matches.
+    crate fn candidates(
+        &self,
+        method: Option<&str>,
+        path: Option<&str>,
+        pattern_path: Option<&str>,
+    ) -> Vec<Uuid> {
+        let mut ids = self.unindexed.clone();
+
+        let by_url: Vec<&HashMap<Option<String>, Vec<Uuid>>> = match path {
+            Some(path) => self.exact.get(path).into_iter().collect(),
+            None => self.exact.values().collect(),
+        };
+        for by_method in by_url {
+            match method {
+                Some(method) => {
+                    for key in &[Some(method.to_string()), None] {
+                        if let Some(method_ids) = by_method.get(key) {
+                            ids.extend(method_ids);
+                        }
+                    }
+                }
+                None => ids.extend(by_method.values().flatten()),
+            }
+        }
+
+        match (pattern_path, &self.patterns) {
+            (Some(path), Some(patterns)) => {
+                ids.extend(
+                    patterns
+                        .matches(path)
+                        .into_iter()
+                        .map(|idx| self.pattern_ids[idx]),
+                );
+                ids.extend(&self.pattern_ids[self.indexed_patterns..]);
+            }
+            _ => ids.extend(&self.pattern_ids),
+        }
+
+        ids
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Index;
+    use crate::config::pattern::test::pattern;
+    use crate::config::{Mapping, Request};
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use uuid::Uuid;
+
+    fn mapping(id: u128, request: Request) -> (Uuid, Arc<Mapping>) {
+        let id = Uuid::from_u128(id);
+        (
+            id,
+            Arc::new(Mapping::builder().id(id).when(request).build()),
+        )
+    }
+
+    fn sorted(mut ids: Vec<Uuid>) -> Vec<Uuid> {
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn candidates() {
+        let mappings: HashMap<Uuid, Arc<Mapping>> = vec![
+            mapping(1, Request::get("/users")),
+            mapping(2, Request::post("/users").with_header("Accept", "*/*")),
+            mapping(
+                3,
+                Request::default().with_url_pattern(pattern("^/users/[0-9]+$")),
+            ),
+            mapping(4, Request::default().with_header("X-Any", "any")),
+            mapping(5, Request::get("/orders")),
+        ]
+        .into_iter()
+        .collect();
+        let index = Index::new(&mappings);
+        let id = Uuid::from_u128;
+
+        assert_eq!(
+            sorted(index.candidates(Some("GET"), Some("/users"), Some("/users"))),
+            vec![id(1), id(4)]
+        );
+        assert_eq!(
+            sorted(index.candidates(Some("GET"), Some("/users/12"), Some("/users/12"))),
+            vec![id(3), id(4)]
+        );
+        assert_eq!(
+            sorted(index.candidates(None, Some("/users"), None)),
+            vec![id(1), id(2), id(3), id(4)]
+        );
+        assert_eq!(
+            sorted(index.candidates(None, None, None)),
+            vec![id(1), id(2), id(3), id(4), id(5)]
+        );
+    }
+
+    #[test]
+    fn insert() {
+        let mut index = Index::default();
+        let id = Uuid::from_u128;
+        let url_patterns = [
+            "^/users/[0-9]+$",
+            "^/orders/[0-9]+$",
+            "^/items/[0-9]+$",
+            "^/carts/[0-9]+$",
+        ];
+        for (idx, url_pattern) in url_patterns.iter().enumerate() {
+            let (pattern_id, pattern_mapping) = mapping(
+                idx as u128 + 1,
+                Request::default().with_url_pattern(pattern(url_pattern)),
+            );
+            index.insert(pattern_id, &pattern_mapping);
+        }
+        let (exact_id, exact) = mapping(5, Request::get("/users"));
+        index.insert(exact_id, &exact);
+
+        // The last pattern is not in the regex set yet, so it is always a candidate.
+        assert_eq!(
+            sorted(index.candidates(Some("GET"), Some("/users/12"), Some("/users/12"))),
+            vec![id(1), id(4)]
+        );
+        assert_eq!(
+            sorted(index.candidates(Some("GET"), Some("/users"), Some("/users"))),
+            vec![id(4), id(5)]
+        );
+    }
+}
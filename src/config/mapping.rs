@@ -7,34 +7,143 @@
 // modified, or distributed except according to those terms.
 
 //! `libdeadmock` request/response mapping
-use crate::config::{Request, Response};
-use getset::{Getters, MutGetters};
+use crate::config::{Request, Response, Times};
+use getset::{Getters, MutGetters, Setters};
 use serde_derive::{Deserialize, Serialize};
 use std::cmp::{Ord, Ordering};
 use std::fmt;
+use uuid::Uuid;
 
 /// `libdeadmock` mapping configuration
 #[derive(
-    Clone, Debug, Default, Deserialize, Eq, Getters, Hash, MutGetters, PartialEq, Serialize,
+    Clone, Debug, Default, Deserialize, Eq, Getters, Hash, MutGetters, PartialEq, Serialize, Setters,
 )]
 pub struct Mapping {
+    /// The stable identifier of this mapping.  If this is not set, one is derived from the
+    /// mapping file path when the mapping is loaded.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    #[set = "pub"]
+    id: Option<Uuid>,
     /// The name of this mapping.
     #[get = "pub"]
     name: String,
     /// The priority of this mapping.  Lower takes priority over higher in the case of multiple matches.
     #[get = "pub"]
-    priority: u8,
+    priority: u32,
     /// The request matching configuration.
     #[get = "pub"]
     request: Request,
     /// The response configuration.
     #[get = "pub"]
     response: Response,
+    /// How many times this mapping is expected to be matched, if it is verified.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    #[set = "pub"]
+    expect: Option<Times>,
+}
+
+impl Mapping {
+    /// Start building a mapping.
+    ///
+    /// # Example
+    /// ```
+    /// # use libdeadmock::config::{Mapping, Request, Response};
+    /// # use serde_json::json;
+    /// #
+    /// # fn main() {
+    ///     let mapping = Mapping::builder()
+    ///         .name("Get Users")
+    ///         .priority(1)
+    ///         .when(Request::get("/users"))
+    ///         .respond(Response::with_status(200).with_json_body(&json!([])))
+    ///         .build();
+    ///     assert_eq!(mapping.name(), "Get Users");
+    /// # }
+    /// ```
+    pub fn builder() -> MappingBuilder {
+        MappingBuilder::default()
+    }
+
+    /// Create a new mapping from the given parts.
+    crate fn new(name: String, priority: u32, request: Request, response: Response) -> Self {
+        Self {
+            id: None,
+            name,
+            priority,
+            request,
+            response,
+            expect: None,
+        }
+    }
+}
+
+/// A builder for a `Mapping`.
+#[derive(Clone, Debug, Default)]
+pub struct MappingBuilder {
+    mapping: Mapping,
 }
 
+impl MappingBuilder {
+    /// Set the stable identifier of the mapping.
+    pub fn id(mut self, id: Uuid) -> Self {
+        self.mapping.id = Some(id);
+        self
+    }
+
+    /// Set the name of the mapping.
+    pub fn name<N: Into<String>>(mut self, name: N) -> Self {
+        self.mapping.name = name.into();
+        self
+    }
+
+    /// Set the priority of the mapping.  Lower takes priority over higher.
+    pub fn priority(mut self, priority: u32) -> Self {
+        self.mapping.priority = priority;
+        self
+    }
+
+    /// Set the request matching configuration of the mapping.
+    pub fn when(mut self, request: Request) -> Self {
+        self.mapping.request = request;
+        self
+    }
+
+    /// Set the response configuration of the mapping.
+    pub fn respond(mut self, response: Response) -> Self {
+        self.mapping.response = response;
+        self
+    }
+
+    /// Set how many times the mapping is expected to be matched.
+    pub fn expect(mut self, times: Times) -> Self {
+        self.mapping.expect = Some(times);
+        self
+    }
+
+    /// Build the mapping.
+    pub fn build(self) -> Mapping {
+        self.mapping
+    }
+}
+
+/// Mappings are ordered by
+///
+/// 1. priority, lower first.
+/// 2. specificity, the mapping with more request matching criteria first.
+/// 3. name, so ties between equally specific mappings resolve the same way on every run.
+/// 4. id, for mappings with the same name.  Mappings added in code without an explicit id get a
+///    random id, so give those distinct names when their order matters.
 impl Ord for Mapping {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.priority.cmp(&other.priority)
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.request.specificity().cmp(&self.request.specificity()))
+            .then_with(|| self.name.cmp(&other.name))
+            .then_with(|| self.id.cmp(&other.id))
     }
 }
 
@@ -57,7 +166,9 @@ crate mod test {
     use super::Mapping;
     use crate::config::request::test::{full_request, partial_request};
     use crate::config::response::test::{full_response, partial_response};
+    use crate::config::Request;
     use std::collections::BTreeMap;
+    use uuid::Uuid;
 
     const EMPTY_MAPPING: &str = r#"{"name":"","priority":0,"request":{},"response":{}}"#;
     const PARTIAL_MAPPING: &str = r#"{"name":"Test","priority":10,"request":{"method":"GET","url":"http://a.url.com"},"response":{"status":200,"headers":[{"key":"Content-Type","value":"application/json"}],"proxy_base_url":"http://cdcproxy.kroger.com"}}"#;
@@ -151,10 +262,45 @@ value = "application/json"
             assert!(false, "There was already an entry!");
         }
 
-        let priorities: Vec<(u8, &str)> = mappings.iter().map(|(k, v)| (k.priority, *v)).collect();
+        let priorities: Vec<(u32, &str)> = mappings.iter().map(|(k, v)| (k.priority, *v)).collect();
         assert_eq!(priorities, vec![(1, "second"), (3, "third"), (5, "first")]);
     }
 
+    #[test]
+    fn tie_break() {
+        let mut wide = Mapping::default();
+        wide.priority = 1;
+        wide.request = Request::get("/users");
+
+        let mut specific = wide.clone();
+        specific.request = Request::get("/users").with_header("Accept", "application/json");
+        assert!(
+            specific < wide,
+            "The more specific mapping should sort first"
+        );
+
+        let mut alpha = wide.clone();
+        alpha.name = "alpha".to_string();
+        alpha.id = Some(Uuid::from_u128(2));
+        let mut beta = wide.clone();
+        beta.name = "beta".to_string();
+        beta.id = Some(Uuid::from_u128(1));
+        assert!(alpha < beta, "The name should break ties before the id");
+
+        let mut low_id = wide.clone();
+        low_id.id = Some(Uuid::from_u128(1));
+        let mut high_id = wide.clone();
+        high_id.id = Some(Uuid::from_u128(2));
+        assert!(
+            low_id < high_id,
+            "The lower id should break ties between equal names"
+        );
+
+        let mut bumped = specific.clone();
+        bumped.priority = 70_000;
+        assert!(wide < bumped, "Priority should win over specificity");
+    }
+
     #[test]
     fn serialize_empty_mapping() {
         if let Ok(serialized) = serde_json::to_string(&Mapping::default()) {
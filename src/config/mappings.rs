@@ -7,62 +7,296 @@
 // modified, or distributed except according to those terms.
 
 //! `libdeadmock` request/response mappings
+use crate::config::index::Index;
 use crate::config::Mapping;
-use crate::error::Error::{self, MappingKeyCollision};
+use crate::error::Error::{self, DuplicateMappingId, InvalidMappingFile, MappingKeyCollision};
 use crate::util;
 use clap::ArgMatches;
 use getset::Getters;
-use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use slog::{warn, Logger};
+use slog_try::try_warn;
 use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::fs::File;
 use std::io::{BufReader, Read};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use toml;
 use uuid::Uuid;
 
+/// The mapping file formats, chosen by file extension.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Option<Self> {
+        match path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(str::to_lowercase)
+            .as_ref()
+            .map(String::as_str)
+        {
+            Some("json") => Some(Format::Json),
+            Some("toml") => Some(Format::Toml),
+            Some("yaml") | Some("yml") => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Parse the file into a format-neutral value, so its shape can be checked before it is
+    /// deserialized into mappings.
+    fn to_value(self, buffer: &[u8]) -> Result<Value, String> {
+        match self {
+            Format::Json => serde_json::from_slice(buffer).map_err(|e| e.to_string()),
+            Format::Toml => toml::from_slice(buffer).map_err(|e| e.to_string()),
+            Format::Yaml => serde_yaml::from_slice(buffer).map_err(|e| e.to_string()),
+        }
+    }
+
+    /// Parse the file into its mappings.
+    ///
+    /// A file holds either a single mapping, a `mappings` array (`[[mappings]]` in TOML), or a
+    /// top-level JSON array or YAML list.
+    fn parse(self, buffer: &[u8]) -> Result<Vec<Mapping>, String> {
+        let value = match self.to_value(buffer)? {
+            Value::Object(mut table) => match table.remove("mappings") {
+                Some(mappings) => mappings,
+                None => Value::Object(table),
+            },
+            value => value,
+        };
+
+        if value.is_array() {
+            serde_json::from_value(value).map_err(|e| e.to_string())
+        } else {
+            serde_json::from_value(value)
+                .map(|mapping| vec![mapping])
+                .map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// The path of a mapping file relative to the mappings directory, with `/` separators so the
+/// derived ids are the same on every platform.
+fn relative_path(mappings_path: &Path, path: &Path) -> String {
+    path.strip_prefix(mappings_path)
+        .unwrap_or(path)
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Derive a stable (v5) id for the mapping at the given index in the given mapping file.
+fn derived_id(relative_path: &str, index: usize) -> Uuid {
+    Uuid::new_v5(
+        &Uuid::NAMESPACE_URL,
+        format!("deadmock:mappings/{}#{}", relative_path, index).as_bytes(),
+    )
+}
+
 /// A map of `Mappings`.   Each is stored by `Uuid`.
-#[derive(Clone, Debug, Default, Deserialize, Eq, Getters, PartialEq, Serialize)]
+///
+/// The mappings are indexed by method and exact url, and by url pattern, as they are added, so
+/// a request is only matched against the mappings that could match it.
+#[derive(Clone, Debug, Default, Getters)]
 pub struct Mappings {
     /// The private inner hashmap.
     #[get = "pub"]
-    inner: HashMap<Uuid, Mapping>,
+    inner: HashMap<Uuid, Arc<Mapping>>,
+    /// The lookup index over `inner`.
+    index: Index,
 }
 
-impl<'a> TryFrom<&'a ArgMatches<'a>> for Mappings {
-    type Error = Error;
+impl PartialEq for Mappings {
+    /// The index is derived from the mappings, so only the mappings are compared.
+    fn eq(&self, other: &Self) -> bool {
+        self.inner == other.inner
+    }
+}
 
-    fn try_from(matches: &'a ArgMatches<'_>) -> Result<Self, Error> {
+impl Eq for Mappings {}
+
+impl Mappings {
+    /// Load the mappings from every file under the given directory.
+    ///
+    /// The parser is chosen from the file extension (`.toml`, `.json`, `.yaml`, or `.yml`).
+    /// Files with any other extension are skipped with a warning to `stderr`.
+    ///
+    /// A file holds either a single mapping, or many mappings as a `mappings` array (`[[mappings]]`
+    /// in TOML) or as a top-level JSON array or YAML list.
+    ///
+    /// Each mapping is stored by its `id`.  Mappings without one get an id derived from the file
+    /// path (relative to `mappings_path`) and their index in the file, so it is the same across
+    /// restarts.  Duplicate ids are an error.
+    pub fn load(mappings_path: &Path, stderr: &Option<Logger>) -> Result<Self, Error> {
         let mut mappings = Self::default();
+        // The file each mapping came from, to name both files on a collision.
+        let mut sources: HashMap<Uuid, PathBuf> = HashMap::new();
 
-        let mappings_path = if let Some(mappings_path) = matches.value_of("mappings_path") {
-            PathBuf::from(mappings_path).join("mappings")
-        } else {
-            PathBuf::from("mappings")
-        };
+        util::visit_dirs(mappings_path, &mut |entry| -> Result<(), Error> {
+            let path = entry.path();
+            let format = if let Some(format) = Format::from_path(&path) {
+                format
+            } else {
+                try_warn!(stderr, "Skipping '{}', not a mapping file", path.display());
+                return Ok(());
+            };
 
-        util::visit_dirs(&mappings_path, &mut |entry| -> Result<(), Error> {
-            let f = File::open(entry.path())?;
+            let f = File::open(&path)?;
             let mut reader = BufReader::new(f);
             let mut buffer = Vec::new();
             let _bytes_read = reader.read_to_end(&mut buffer)?;
-            let mapping: Mapping = toml::from_slice(&buffer)?;
-            if let Some(_v) = mappings.inner.insert(Uuid::new_v4(), mapping) {
-                Err(MappingKeyCollision)
-            } else {
-                Ok(())
+            let file_mappings = format
+                .parse(&buffer)
+                .map_err(|e| InvalidMappingFile(path.clone(), e))?;
+            let relative_path = relative_path(mappings_path, &path);
+            for (index, mut mapping) in file_mappings.into_iter().enumerate() {
+                let id = mapping
+                    .id()
+                    .unwrap_or_else(|| derived_id(&relative_path, index));
+                let _ = mapping.set_id(Some(id));
+                if let Some(first_path) = sources.get(&id) {
+                    return Err(MappingKeyCollision(id, first_path.clone(), path.clone()));
+                }
+                let _ = sources.insert(id, path.clone());
+                let _ = mappings.inner.insert(id, Arc::new(mapping));
             }
+            Ok(())
         })?;
+        mappings.index = Index::new(&mappings.inner);
         Ok(mappings)
     }
+
+    /// Add the given mapping, i.e. one built in code.
+    ///
+    /// The mapping is stored by its `id`, or by a new random id if it doesn't have one.  The id
+    /// is returned.  A pattern that doesn't compile is an error.
+    pub fn insert(&mut self, mut mapping: Mapping) -> Result<Uuid, Error> {
+        let id = mapping.id().unwrap_or_else(Uuid::new_v4);
+        if self.inner.contains_key(&id) {
+            return Err(DuplicateMappingId(id));
+        }
+        for (_field, pattern) in mapping.request().patterns() {
+            pattern.check()?;
+        }
+
+        let _ = mapping.set_id(Some(id));
+        self.index.insert(id, &mapping);
+        let _ = self.inner.insert(id, Arc::new(mapping));
+        Ok(id)
+    }
+
+    /// The mappings that could match a request with the given method and path.
+    ///
+    /// See `Index::candidates` for when each part of the request is used for the lookup.
+    crate fn candidates(
+        &self,
+        method: Option<&str>,
+        path: Option<&str>,
+        pattern_path: Option<&str>,
+    ) -> Vec<&Arc<Mapping>> {
+        self.index
+            .candidates(method, path, pattern_path)
+            .iter()
+            .filter_map(|id| self.inner.get(id))
+            .collect()
+    }
+}
+
+impl<'a> TryFrom<&'a ArgMatches<'a>> for Mappings {
+    type Error = Error;
+
+    /// Load the mappings from the `mappings` directory under `mappings_path`.
+    ///
+    /// Use `Mappings::load` to have skipped files logged.
+    fn try_from(matches: &'a ArgMatches<'_>) -> Result<Self, Error> {
+        let mappings_path = if let Some(mappings_path) = matches.value_of("mappings_path") {
+            PathBuf::from(mappings_path).join("mappings")
+        } else {
+            PathBuf::from("mappings")
+        };
+
+        Self::load(&mappings_path, &None)
+    }
 }
 
 #[cfg(test)]
 crate mod test {
-    use super::Mappings;
+    use super::{derived_id, relative_path, Format, Mappings};
+    use crate::config::{validate, Mapping, Request, Response};
     use crate::error::Error;
     use clap::{App, Arg};
     use std::convert::TryFrom;
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use uuid::Uuid;
+
+    const MAPPING_JSON: &str = r#"{
+  "name": "json",
+  "priority": 1,
+  "request": { "method": "GET", "url": "/json" },
+  "response": { "status": 200 }
+}"#;
+    const MAPPING_YAML: &str = "name: yaml
+priority: 1
+request:
+  method: GET
+  url: /yaml
+response:
+  status: 200
+";
+    const MAPPINGS_TOML: &str = r#"[[mappings]]
+name = "toml-1"
+priority = 1
+
+[mappings.request]
+url = "/toml-1"
+
+[mappings.response]
+status = 200
+
+[[mappings]]
+name = "toml-2"
+priority = 2
+
+[mappings.request]
+url = "/toml-2"
+
+[mappings.response]
+status = 200
+"#;
+    const MAPPINGS_JSON: &str = r#"[
+  { "name": "json-1", "priority": 1, "request": { "url": "/json-1" }, "response": {} },
+  { "name": "json-2", "priority": 2, "request": { "url": "/json-2" }, "response": {} }
+]"#;
+    const MAPPINGS_YAML: &str = "- name: yaml-1
+  priority: 1
+  request:
+    url: /yaml-1
+  response: {}
+- name: yaml-2
+  priority: 2
+  request:
+    url: /yaml-2
+  response: {}
+";
+    const MAPPINGS_BAD_TOML: &str = r#"[[mappings]]
+name = "no-priority"
+
+[mappings.request]
+url = "/no-priority"
+
+[mappings.response]
+status = 200
+"#;
 
     crate fn test_mappings() -> Result<Mappings, Error> {
         let args = vec!["test", "-m", "tests"];
@@ -82,4 +316,205 @@ crate mod test {
 
         Ok(Mappings::try_from(&matches)?)
     }
+
+    #[test]
+    fn format_from_extension() {
+        assert_eq!(Format::from_path(Path::new("a.toml")), Some(Format::Toml));
+        assert_eq!(Format::from_path(Path::new("a.JSON")), Some(Format::Json));
+        assert_eq!(Format::from_path(Path::new("a.yaml")), Some(Format::Yaml));
+        assert_eq!(Format::from_path(Path::new("a.yml")), Some(Format::Yaml));
+        assert_eq!(Format::from_path(Path::new("README.md")), None);
+        assert_eq!(Format::from_path(Path::new("README")), None);
+    }
+
+    #[test]
+    fn load_mixed_formats() {
+        let dir = env::temp_dir().join(format!("deadmock-mappings-{}", Uuid::new_v4()));
+        assert!(fs::create_dir_all(&dir).is_ok());
+        assert!(fs::write(dir.join("a.json"), MAPPING_JSON).is_ok());
+        assert!(fs::write(dir.join("b.yml"), MAPPING_YAML).is_ok());
+        assert!(fs::write(dir.join("README.md"), "# Mappings").is_ok());
+
+        match Mappings::load(&dir, &None) {
+            Ok(mappings) => {
+                let mut names: Vec<&String> = mappings.inner().values().map(|m| m.name()).collect();
+                names.sort();
+                assert_eq!(names, vec!["json", "yaml"]);
+            }
+            Err(e) => assert!(false, e.to_string()),
+        }
+
+        assert!(fs::write(dir.join("c.json"), "{ not json").is_ok());
+        match Mappings::load(&dir, &None) {
+            Ok(_) => assert!(false, "Expected an invalid mapping file error"),
+            Err(e) => assert!(e.to_string().contains("c.json")),
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        let dir = env::temp_dir().join(format!("deadmock-mappings-{}", Uuid::new_v4()));
+        assert!(fs::create_dir_all(&dir).is_ok());
+        let invalid = MAPPING_JSON.replacen(r#""url": "/json""#, r#""url_pattern": "*.json""#, 1);
+        assert!(fs::write(dir.join("a.json"), invalid).is_ok());
+
+        // The pattern is reported when the mappings are validated, not as they are loaded.
+        match Mappings::load(&dir, &None) {
+            Ok(mappings) => {
+                let problems = validate(&mappings, &dir);
+                assert_eq!(problems.len(), 1, "{:?}", problems);
+                assert!(problems[0]
+                    .message()
+                    .starts_with("invalid url_pattern '*.json'"));
+
+                let mut inserted = Mappings::default();
+                for mapping in mappings.inner().values() {
+                    match inserted.insert(Mapping::clone(mapping)) {
+                        Ok(_) => assert!(false, "Expected an invalid pattern error"),
+                        Err(Error::InvalidPattern(_)) => {}
+                        Err(e) => assert!(false, e.to_string()),
+                    }
+                }
+            }
+            Err(e) => assert!(false, e.to_string()),
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn many_mappings_per_file() {
+        let toml_mappings = Format::Toml.parse(MAPPINGS_TOML.as_bytes());
+        let json_mappings = Format::Json.parse(MAPPINGS_JSON.as_bytes());
+        let yaml_mappings = Format::Yaml.parse(MAPPINGS_YAML.as_bytes());
+
+        for (prefix, parsed) in vec![
+            ("toml", toml_mappings),
+            ("json", json_mappings),
+            ("yaml", yaml_mappings),
+        ] {
+            match parsed {
+                Ok(mappings) => {
+                    let names: Vec<&String> = mappings.iter().map(|m| m.name()).collect();
+                    assert_eq!(
+                        names,
+                        vec![&format!("{}-1", prefix), &format!("{}-2", prefix)]
+                    );
+                }
+                Err(e) => assert!(false, e),
+            }
+        }
+
+        match Format::Json.parse(MAPPING_JSON.as_bytes()) {
+            Ok(mappings) => assert_eq!(mappings.len(), 1),
+            Err(e) => assert!(false, e),
+        }
+    }
+
+    #[test]
+    fn many_mappings_error() {
+        match Format::Toml.parse(MAPPINGS_BAD_TOML.as_bytes()) {
+            Ok(_) => assert!(false, "Expected a missing priority error"),
+            Err(e) => assert!(e.contains("priority"), e),
+        }
+    }
+
+    #[test]
+    fn stable_ids() {
+        let dir = env::temp_dir().join(format!("deadmock-mappings-{}", Uuid::new_v4()));
+        let id = "0c2a9b62-1a4f-4a5e-9f4e-5b1d0d3c7a11";
+        assert!(fs::create_dir_all(dir.join("nested")).is_ok());
+        assert!(fs::write(dir.join("nested").join("many.yml"), MAPPINGS_YAML).is_ok());
+        assert!(fs::write(
+            dir.join("a.json"),
+            MAPPING_JSON.replacen("{", &format!(r#"{{ "id": "{}","#, id), 1)
+        )
+        .is_ok());
+
+        let first = Mappings::load(&dir, &None);
+        let second = Mappings::load(&dir, &None);
+        match (first, second) {
+            (Ok(first), Ok(second)) => {
+                assert_eq!(first, second);
+                assert_eq!(first.inner().len(), 3);
+                let declared = Uuid::parse_str(id).expect("invalid uuid");
+                assert_eq!(
+                    first.inner().get(&declared).map(|m| m.name().as_str()),
+                    Some("json")
+                );
+                let derived = derived_id("nested/many.yml", 1);
+                assert_eq!(
+                    first.inner().get(&derived).map(|m| m.name().as_str()),
+                    Some("yaml-2")
+                );
+                assert_eq!(
+                    first.inner().get(&derived).and_then(|m| *m.id()),
+                    Some(derived)
+                );
+            }
+            _ => assert!(false, "Not expected to error!"),
+        }
+
+        assert!(fs::write(
+            dir.join("b.json"),
+            MAPPING_JSON.replacen("{", &format!(r#"{{ "id": "{}","#, id), 1)
+        )
+        .is_ok());
+        match Mappings::load(&dir, &None) {
+            Ok(_) => assert!(false, "Expected a mapping key collision"),
+            Err(e) => {
+                let message = e.to_string();
+                assert!(message.contains(id));
+                assert!(message.contains("a.json"));
+                assert!(message.contains("b.json"));
+            }
+        }
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn relative_paths() {
+        let root = Path::new("mappings");
+        assert_eq!(
+            relative_path(root, &root.join("a").join("b.toml")),
+            "a/b.toml"
+        );
+        assert_eq!(
+            relative_path(root, Path::new("other/c.toml")),
+            "other/c.toml"
+        );
+    }
+
+    #[test]
+    fn insert() {
+        let mut mappings = Mappings::default();
+        let id = Uuid::new_v4();
+        let mapping = Mapping::builder()
+            .id(id)
+            .name("inserted")
+            .when(Request::get("/inserted"))
+            .respond(Response::with_status(204))
+            .build();
+
+        match mappings.insert(mapping.clone()) {
+            Ok(inserted) => assert_eq!(inserted, id),
+            Err(e) => assert!(false, e.to_string()),
+        }
+        match mappings.insert(mapping) {
+            Ok(_) => assert!(false, "Expected a duplicate mapping id error!"),
+            Err(e) => assert_eq!(e.to_string(), format!("duplicate mapping id '{}'", id)),
+        }
+
+        match mappings.insert(Mapping::builder().name("random").build()) {
+            Ok(inserted) => assert_eq!(
+                mappings.inner().get(&inserted).and_then(|m| *m.id()),
+                Some(inserted)
+            ),
+            Err(e) => assert!(false, e.to_string()),
+        }
+        assert_eq!(mappings.inner().len(), 2);
+    }
 }
@@ -7,20 +7,37 @@
 // modified, or distributed except according to those terms.
 
 //! Configuration for the server.
+crate mod admin;
 crate mod files;
 crate mod header;
+crate mod index;
 crate mod mapping;
 crate mod mappings;
+crate mod pattern;
 crate mod proxy;
+crate mod record;
 crate mod request;
 crate mod response;
 crate mod runtime;
+crate mod times;
+crate mod tls;
+crate mod upstream;
+crate mod url_options;
+crate mod validate;
 
+pub use self::admin::Admin;
 pub use self::files::Files;
 pub use self::header::{Header, HeaderPattern};
-pub use self::mapping::Mapping;
+pub use self::mapping::{Mapping, MappingBuilder};
 pub use self::mappings::Mappings;
+pub use self::pattern::Pattern;
 pub use self::proxy::Proxy;
+pub use self::record::Record;
 pub use self::request::Request;
 pub use self::response::Response;
 pub use self::runtime::Runtime;
+pub use self::times::Times;
+pub use self::tls::{ClientTls, ServerTls};
+pub use self::upstream::Upstream;
+pub use self::url_options::UrlOptions;
+pub use self::validate::{validate, Problem, Severity};
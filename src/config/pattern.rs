@@ -0,0 +1,204 @@
+// Copyright (c) 2018 libdeadmock developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Request matching patterns (regex)
+use crate::error::Error;
+use regex::{Regex, RegexBuilder};
+use serde::de::{Deserialize, Deserializer};
+use serde::ser::{Serialize, Serializer};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+/// A request matching pattern (regex).
+///
+/// The pattern is compiled when it is created or deserialized.  A deserialized pattern that
+/// doesn't compile is kept, never matches, and is reported when the mappings are validated, so
+/// one bad pattern doesn't hide the other problems in the mappings.  Patterns are compared,
+/// hashed, and serialized by their source string.
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    /// The source string of the pattern.
+    source: String,
+    /// The compiled pattern, or why it didn't compile.
+    regex: Result<Regex, regex::Error>,
+    /// Was the pattern compiled to match ignoring case?
+    ignore_case: bool,
+}
+
+impl Pattern {
+    /// Compile the given pattern.
+    ///
+    /// # Example
+    /// ```
+    /// # use libdeadmock::config::Pattern;
+    /// #
+    /// # fn main() {
+    ///     let pattern = Pattern::new("^/users/[0-9]+$").expect("valid pattern");
+    ///     assert!(pattern.is_match("/users/12"));
+    ///     assert!(Pattern::new("*").is_err());
+    /// # }
+    /// ```
+    pub fn new(pattern: &str) -> Result<Self, Error> {
+        let pattern = Self::compile(pattern, false);
+        pattern.check()?;
+        Ok(pattern)
+    }
+
+    /// Compile the given pattern, keeping it even if it doesn't compile.
+    fn compile(source: &str, ignore_case: bool) -> Self {
+        Pattern {
+            source: source.to_string(),
+            regex: RegexBuilder::new(source)
+                .case_insensitive(ignore_case)
+                .build(),
+            ignore_case,
+        }
+    }
+
+    /// Does the pattern match anywhere in the given text?  A pattern that didn't compile never
+    /// matches.
+    pub fn is_match(&self, text: &str) -> bool {
+        self.regex
+            .as_ref()
+            .map_or(false, |regex| regex.is_match(text))
+    }
+
+    /// The source string of the pattern.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Why the pattern didn't compile, if it didn't.
+    crate fn error(&self) -> Option<&regex::Error> {
+        self.regex.as_ref().err()
+    }
+
+    /// Fail if the pattern didn't compile.
+    crate fn check(&self) -> Result<(), Error> {
+        match self.error() {
+            Some(e) => Err(e.clone().into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Compile the same pattern to match, or not, ignoring case.
+    crate fn with_ignore_case(&self, ignore_case: bool) -> Self {
+        Self::compile(&self.source, ignore_case)
+    }
+
+    /// Was the pattern compiled to match ignoring case?
+    crate fn is_ignore_case(&self) -> bool {
+        self.ignore_case
+    }
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(pattern: &str) -> Result<Self, Error> {
+        Self::new(pattern)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_str() == other.as_str()
+    }
+}
+
+impl Eq for Pattern {}
+
+impl Hash for Pattern {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.as_str().hash(state);
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl Serialize for Pattern {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let pattern = String::deserialize(deserializer)?;
+        Ok(Self::compile(&pattern, false))
+    }
+}
+
+#[cfg(test)]
+crate mod test {
+    use super::Pattern;
+
+    crate fn pattern(pattern: &str) -> Pattern {
+        Pattern::new(pattern).expect("Unable to compile the test pattern!")
+    }
+
+    /// A pattern that may not compile, as it would be deserialized.
+    crate fn unchecked(pattern: &str) -> Pattern {
+        Pattern::compile(pattern, false)
+    }
+
+    #[test]
+    fn compile() {
+        assert!(pattern("^application/.*").is_match("application/json"));
+        assert!(!pattern("^application/.*").is_match("text/plain"));
+        assert_eq!(pattern("P.*"), pattern("P.*"));
+        assert_ne!(pattern("P.*"), pattern("G.*"));
+        assert!(Pattern::new("*").is_err());
+        assert!("(unclosed".parse::<Pattern>().is_err());
+
+        let invalid = unchecked("*");
+        assert!(invalid.error().is_some());
+        assert!(invalid.check().is_err());
+        assert!(!invalid.is_match("*"));
+        assert_eq!(invalid, unchecked("*"));
+    }
+
+    #[test]
+    fn ignore_case() {
+        let ignore_case = pattern("^/users/[a-z]+$").with_ignore_case(true);
+        assert!(ignore_case.is_match("/Users/Bob"));
+        assert!(ignore_case.is_ignore_case());
+        assert_eq!(ignore_case.as_str(), "^/users/[a-z]+$");
+        assert!(!pattern("^/users/[a-z]+$").is_match("/Users/Bob"));
+
+        let match_case = ignore_case.with_ignore_case(false);
+        assert!(!match_case.is_match("/Users/Bob"));
+        assert!(!match_case.is_ignore_case());
+        assert!(unchecked("*").with_ignore_case(true).check().is_err());
+    }
+
+    #[test]
+    fn serde() {
+        match serde_json::to_string(&pattern("^/users$")) {
+            Ok(serialized) => assert_eq!(serialized, r#""^/users$""#),
+            Err(e) => assert!(false, e.to_string()),
+        }
+        match serde_json::from_str::<Pattern>(r#""^/users$""#) {
+            Ok(deserialized) => assert_eq!(deserialized, pattern("^/users$")),
+            Err(e) => assert!(false, e.to_string()),
+        }
+        // An invalid pattern still deserializes, to be reported with the other problems.
+        match serde_json::from_str::<Pattern>(r#""*""#) {
+            Ok(deserialized) => {
+                assert_eq!(deserialized, unchecked("*"));
+                assert!(deserialized.check().is_err());
+            }
+            Err(e) => assert!(false, e.to_string()),
+        }
+    }
+}
@@ -7,10 +7,15 @@
 // modified, or distributed except according to those terms.
 
 //! `libdeadmock` proxy configuration
+use crate::config::ClientTls;
 use crate::error::Error::{self, InvalidProxyConfig};
 use clap::ArgMatches;
 use getset::{Getters, Setters};
+use http::Uri;
+use serde_derive::{Deserialize, Serialize};
 use std::convert::TryFrom;
+use std::env;
+use std::net::IpAddr;
 
 /// The proxy configuration for deadmock
 ///
@@ -80,7 +85,8 @@ use std::convert::TryFrom;
 ///     let proxy_config = config::Proxy::new(true, Some("http://a.proxyurl.com".to_string()));
 /// # }
 /// ```
-#[derive(Clone, Debug, Default, Getters, Hash, Eq, PartialEq, Setters)]
+#[derive(Clone, Debug, Default, Deserialize, Getters, Hash, Eq, PartialEq, Serialize, Setters)]
+#[serde(default)]
 pub struct Proxy {
     /// Turn the proxy on.  If this is true, `proxy_url` is required.
     #[get = "pub"]
@@ -98,8 +104,37 @@ pub struct Proxy {
     #[get = "pub"]
     #[set = "pub"]
     proxy_password: Option<String>,
+    /// The proxy url for `https` upstreams.  If this is not set, `proxy_url` is used.
+    #[get = "pub"]
+    #[set = "pub"]
+    https_proxy_url: Option<String>,
+    /// Upstream hosts that bypass the proxy.
+    ///
+    /// Each entry is a host name (which also matches its subdomains), a domain suffix
+    /// (i.e. `.example.com`), an IP address, a CIDR block (i.e. `10.0.0.0/8`), or `*` to bypass
+    /// the proxy for all hosts.
+    #[get = "pub"]
+    #[set = "pub"]
+    no_proxy: Vec<String>,
+    /// Client TLS settings for proxied `https` upstreams.
+    ///
+    /// The entry with the longest matching `base_url` is used for an upstream, falling back to
+    /// the entry without a `base_url`.
+    #[get = "pub"]
+    #[set = "pub"]
+    client_tls: Vec<ClientTls>,
 }
 
+/// The command line arguments for the client TLS entry without a `base_url`.
+const CLIENT_TLS_ARGS: &[&str] = &[
+    "upstream-ca",
+    "upstream-pkcs12",
+    "upstream-pkcs12-password",
+    "upstream-cert",
+    "upstream-key",
+    "upstream-insecure",
+];
+
 impl Proxy {
     /// Create a new minimal proxy configuration.
     ///
@@ -121,43 +156,276 @@ impl Proxy {
             proxy_url,
             proxy_username: None,
             proxy_password: None,
+            https_proxy_url: None,
+            no_proxy: vec![],
+            client_tls: vec![],
         }
     }
-}
 
-impl<'a> TryFrom<&'a ArgMatches<'a>> for Proxy {
-    type Error = Error;
+    /// Create a proxy configuration from the `HTTP_PROXY`, `HTTPS_PROXY`, and `NO_PROXY`
+    /// environment variables (or their lowercase forms).
+    ///
+    /// The proxy is enabled if either proxy url variable is set.
+    pub fn from_env() -> Self {
+        Self::from_vars(|name| {
+            env::var(name)
+                .or_else(|_| env::var(name.to_lowercase()))
+                .ok()
+        })
+    }
 
-    fn try_from(matches: &'a ArgMatches<'a>) -> Result<Self, Error> {
-        let use_proxy = matches.is_present("proxy");
-        let proxy_url = matches.value_of("proxy-url").map(|s| s.to_string());
-        let proxy_username = matches.value_of("proxy-username").map(|s| s.to_string());
-        let proxy_password = matches.value_of("proxy-password").map(|s| s.to_string());
-
-        if use_proxy && proxy_url.is_some() {
-            Ok(Self {
-                proxy_url,
-                use_proxy,
-                proxy_username,
-                proxy_password,
-            })
-        } else if use_proxy && proxy_url.is_none() {
+    fn from_vars<F>(var: F) -> Self
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let proxy_url = var("HTTP_PROXY").filter(|url| !url.is_empty());
+        let https_proxy_url = var("HTTPS_PROXY").filter(|url| !url.is_empty());
+        let no_proxy = var("NO_PROXY").map_or_else(Vec::new, |value| split_no_proxy(&value));
+
+        Self {
+            use_proxy: proxy_url.is_some() || https_proxy_url.is_some(),
+            proxy_url,
+            proxy_username: None,
+            proxy_password: None,
+            https_proxy_url,
+            no_proxy,
+            client_tls: vec![],
+        }
+    }
+
+    /// Check that the configuration is usable, i.e. a proxy url is given if the proxy is on.
+    crate fn check(&self) -> Result<(), Error> {
+        if self.use_proxy && self.proxy_url.is_none() && self.https_proxy_url.is_none() {
             Err(InvalidProxyConfig)
         } else {
-            Ok(Self {
-                proxy_url,
-                use_proxy,
-                proxy_username,
-                proxy_password,
+            Ok(())
+        }
+    }
+
+    /// Override the settings whose command line arguments are given, keeping the rest.
+    ///
+    /// The `upstream-*` arguments override the client TLS entry without a `base_url`, and the
+    /// per-base-url entries are kept.
+    crate fn with_matches<'a>(mut self, matches: &'a ArgMatches<'a>) -> Result<Self, Error> {
+        if matches.is_present("proxy") {
+            self.use_proxy = true;
+        }
+        if let Some(proxy_url) = matches.value_of("proxy-url") {
+            self.proxy_url = Some(proxy_url.to_string());
+        }
+        if let Some(https_proxy_url) = matches.value_of("https-proxy-url") {
+            self.https_proxy_url = Some(https_proxy_url.to_string());
+        }
+        if let Some(proxy_username) = matches.value_of("proxy-username") {
+            self.proxy_username = Some(proxy_username.to_string());
+        }
+        if let Some(proxy_password) = matches.value_of("proxy-password") {
+            self.proxy_password = Some(proxy_password.to_string());
+        }
+        if let Some(values) = matches.values_of("no-proxy") {
+            self.no_proxy = values.flat_map(split_no_proxy).collect();
+        }
+
+        if CLIENT_TLS_ARGS.iter().any(|name| matches.is_present(name)) {
+            let position = self
+                .client_tls
+                .iter()
+                .position(|client_tls| client_tls.base_url().is_none());
+            let global_tls = match position {
+                Some(position) => self.client_tls.remove(position),
+                None => ClientTls::default(),
+            };
+            self.client_tls.push(global_tls.with_matches(matches)?);
+        }
+
+        self.check()?;
+        Ok(self)
+    }
+
+    /// Does the given upstream host bypass the proxy?
+    ///
+    /// # Example
+    /// ```
+    /// # use libdeadmock::config;
+    /// #
+    /// # fn main() {
+    ///     let mut proxy_config = config::Proxy::new(true, Some("http://a.proxyurl.com".to_string()));
+    ///     proxy_config.set_no_proxy(vec![".internal.com".to_string(), "10.0.0.0/8".to_string()]);
+    ///
+    ///     assert!(proxy_config.bypass("api.internal.com"));
+    ///     assert!(proxy_config.bypass("10.1.2.3"));
+    ///     assert!(!proxy_config.bypass("api.external.com"));
+    /// # }
+    /// ```
+    pub fn bypass(&self, host: &str) -> bool {
+        let host = host
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_lowercase();
+        let ip = host.parse::<IpAddr>().ok();
+
+        self.no_proxy.iter().any(|entry| {
+            let entry = entry.trim().to_lowercase();
+
+            if entry == "*" {
+                true
+            } else if entry.contains('/') {
+                ip.map_or(false, |ip| in_cidr(ip, &entry))
+            } else if let Ok(entry_ip) = entry.parse::<IpAddr>() {
+                ip == Some(entry_ip)
+            } else {
+                let domain = entry.trim_start_matches('*').trim_start_matches('.');
+                !domain.is_empty() && (host == domain || host.ends_with(&format!(".{}", domain)))
+            }
+        })
+    }
+
+    /// The proxy url to use for the given upstream url, if any.
+    ///
+    /// `mapping_override` is the per-mapping override from `config::Response`.  `Some(false)`
+    /// bypasses the proxy, and `Some(true)` uses the proxy even if the host is in `no_proxy`.
+    pub fn proxy_url_for(&self, url: &Uri, mapping_override: Option<bool>) -> Option<&str> {
+        let use_proxy = match mapping_override {
+            Some(use_proxy) => use_proxy,
+            None => self.use_proxy && !url.host().map_or(false, |host| self.bypass(host)),
+        };
+
+        if !use_proxy {
+            None
+        } else if url.scheme_str() == Some("https") {
+            self.https_proxy_url
+                .as_ref()
+                .or_else(|| self.proxy_url.as_ref())
+                .map(String::as_str)
+        } else {
+            self.proxy_url.as_ref().map(String::as_str)
+        }
+    }
+
+    /// The client TLS settings to use for the given upstream url, if any.
+    ///
+    /// # Example
+    /// ```
+    /// # use libdeadmock::config;
+    /// #
+    /// # fn main() {
+    ///     let mut staging_tls = config::ClientTls::default();
+    ///     staging_tls.set_base_url(Some("https://staging.internal.com".to_string()));
+    ///     staging_tls.set_accept_invalid_certs(true);
+    ///
+    ///     let mut proxy_config = config::Proxy::default();
+    ///     proxy_config.set_client_tls(vec![config::ClientTls::default(), staging_tls.clone()]);
+    ///
+    ///     assert_eq!(
+    ///         proxy_config.client_tls_for("https://staging.internal.com/api"),
+    ///         Some(&staging_tls)
+    ///     );
+    ///     assert_eq!(
+    ///         proxy_config.client_tls_for("https://api.external.com"),
+    ///         Some(&config::ClientTls::default())
+    ///     );
+    /// # }
+    /// ```
+    pub fn client_tls_for(&self, url: &str) -> Option<&ClientTls> {
+        let url = url.parse::<Uri>().ok();
+
+        self.client_tls
+            .iter()
+            .filter_map(|client_tls| match client_tls.base_url() {
+                Some(base_url) => url
+                    .as_ref()
+                    .and_then(|url| base_url_match(base_url, url))
+                    .map(|len| (len + 1, client_tls)),
+                None => Some((0, client_tls)),
             })
+            .max_by_key(|(len, _)| *len)
+            .map(|(_, client_tls)| client_tls)
+    }
+}
+
+/// Does the given base url cover the given upstream url?  If so, the length of the base url
+/// path is returned, so the most specific base url can be picked.
+///
+/// The scheme, host, and port must be the same, and the base url path must be a prefix of the
+/// upstream path that ends on a `/` boundary, i.e. `/api` covers `/api/v1` but not `/apiary`.
+fn base_url_match(base_url: &str, url: &Uri) -> Option<usize> {
+    let base_url = base_url.parse::<Uri>().ok()?;
+    let same_origin = base_url.scheme_str().map(str::to_lowercase)
+        == url.scheme_str().map(str::to_lowercase)
+        && base_url.host().map(str::to_lowercase) == url.host().map(str::to_lowercase)
+        && port(&base_url) == port(url);
+
+    let base_path = base_url.path().trim_end_matches('/');
+    let path = url.path();
+    let on_boundary = path.starts_with(base_path)
+        && (path.len() == base_path.len() || path[base_path.len()..].starts_with('/'));
+
+    if same_origin && on_boundary {
+        Some(base_path.len())
+    } else {
+        None
+    }
+}
+
+/// The port of the given url, or the default port for its scheme.
+fn port(url: &Uri) -> Option<u16> {
+    url.port_part()
+        .map(|port| port.as_u16())
+        .or_else(|| match url.scheme_str() {
+            Some(scheme) if scheme.eq_ignore_ascii_case("https") => Some(443),
+            Some(scheme) if scheme.eq_ignore_ascii_case("http") => Some(80),
+            _ => None,
+        })
+}
+
+/// Split a comma separated `no_proxy` list.
+fn split_no_proxy(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| entry.to_string())
+        .collect()
+}
+
+/// Is the given ip address in the given CIDR block?
+fn in_cidr(ip: IpAddr, cidr: &str) -> bool {
+    let mut parts = cidr.splitn(2, '/');
+    let network = parts
+        .next()
+        .and_then(|network| network.parse::<IpAddr>().ok());
+    let prefix = parts.next().and_then(|prefix| prefix.parse::<u32>().ok());
+
+    match (ip, network, prefix) {
+        (IpAddr::V4(ip), Some(IpAddr::V4(network)), Some(prefix)) if prefix <= 32 => {
+            let mask = u32::max_value().checked_shl(32 - prefix).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(network) & mask
         }
+        (IpAddr::V6(ip), Some(IpAddr::V6(network)), Some(prefix)) if prefix <= 128 => {
+            let mask = u128::max_value().checked_shl(128 - prefix).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(network) & mask
+        }
+        _ => false,
+    }
+}
+
+impl<'a> TryFrom<&'a ArgMatches<'a>> for Proxy {
+    type Error = Error;
+
+    /// Only the command line arguments are read.  The proxy environment variables are layered
+    /// on by `Runtime::with_env`.
+    fn try_from(matches: &'a ArgMatches<'a>) -> Result<Self, Error> {
+        Self::default().with_matches(matches)
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::Proxy;
+    use crate::config::tls::test::staging_client_tls;
+    use crate::config::ClientTls;
     use clap::{App, Arg};
+    use http::Uri;
     use std::convert::TryFrom;
 
     fn test_cli() -> App<'static, 'static> {
@@ -281,4 +549,162 @@ mod test {
             Err(e) => assert_eq!(format!("{}", e), "invalid proxy configuration!"),
         }
     }
+
+    fn no_proxy_config() -> Proxy {
+        let mut proxy_config = Proxy::new(true, Some("http://a.proxy.com".to_string()));
+        let _ = proxy_config.set_no_proxy(vec![
+            "localhost".to_string(),
+            ".internal.com".to_string(),
+            "corp.net".to_string(),
+            "10.0.0.0/8".to_string(),
+            "192.168.1.10".to_string(),
+            "fd00::/8".to_string(),
+        ]);
+        proxy_config
+    }
+
+    #[test]
+    fn bypass_hosts_and_domains() {
+        let proxy_config = no_proxy_config();
+        assert!(proxy_config.bypass("localhost"));
+        assert!(proxy_config.bypass("LOCALHOST"));
+        assert!(proxy_config.bypass("api.internal.com"));
+        assert!(proxy_config.bypass("internal.com"));
+        assert!(proxy_config.bypass("corp.net"));
+        assert!(proxy_config.bypass("api.corp.net"));
+        assert!(!proxy_config.bypass("notcorp.net"));
+        assert!(!proxy_config.bypass("api.external.com"));
+    }
+
+    #[test]
+    fn bypass_ips_and_cidrs() {
+        let proxy_config = no_proxy_config();
+        assert!(proxy_config.bypass("10.1.2.3"));
+        assert!(!proxy_config.bypass("11.1.2.3"));
+        assert!(proxy_config.bypass("192.168.1.10"));
+        assert!(!proxy_config.bypass("192.168.1.11"));
+        assert!(proxy_config.bypass("[fd12::1]"));
+        assert!(!proxy_config.bypass("[fe80::1]"));
+    }
+
+    #[test]
+    fn bypass_all() {
+        let mut proxy_config = Proxy::new(true, Some("http://a.proxy.com".to_string()));
+        let _ = proxy_config.set_no_proxy(vec!["*".to_string()]);
+        assert!(proxy_config.bypass("api.external.com"));
+    }
+
+    #[test]
+    fn proxy_url_for() {
+        let mut proxy_config = no_proxy_config();
+        let _ = proxy_config.set_https_proxy_url(Some("http://a.secure.proxy.com".to_string()));
+        let external: Uri = "http://api.external.com/a".parse().expect("invalid uri");
+        let external_tls: Uri = "https://api.external.com/a".parse().expect("invalid uri");
+        let internal: Uri = "http://api.internal.com/a".parse().expect("invalid uri");
+
+        assert_eq!(
+            proxy_config.proxy_url_for(&external, None),
+            Some("http://a.proxy.com")
+        );
+        assert_eq!(
+            proxy_config.proxy_url_for(&external_tls, None),
+            Some("http://a.secure.proxy.com")
+        );
+        assert_eq!(proxy_config.proxy_url_for(&internal, None), None);
+        assert_eq!(proxy_config.proxy_url_for(&external, Some(false)), None);
+        assert_eq!(
+            proxy_config.proxy_url_for(&internal, Some(true)),
+            Some("http://a.proxy.com")
+        );
+        assert_eq!(Proxy::default().proxy_url_for(&external, None), None);
+    }
+
+    #[test]
+    fn client_tls_for() {
+        let mut proxy_config = Proxy::default();
+        assert_eq!(
+            proxy_config.client_tls_for("https://api.external.com"),
+            None
+        );
+
+        let mut staging_api_tls = staging_client_tls();
+        let _ = staging_api_tls.set_base_url(Some("https://staging.internal.com/api".to_string()));
+        let _ = proxy_config.set_client_tls(vec![
+            staging_api_tls.clone(),
+            ClientTls::default(),
+            staging_client_tls(),
+        ]);
+
+        assert_eq!(
+            proxy_config.client_tls_for("https://staging.internal.com/api/v1"),
+            Some(&staging_api_tls)
+        );
+        assert_eq!(
+            proxy_config.client_tls_for("https://staging.internal.com/status"),
+            Some(&staging_client_tls())
+        );
+        assert_eq!(
+            proxy_config.client_tls_for("https://api.external.com"),
+            Some(&ClientTls::default())
+        );
+    }
+
+    #[test]
+    fn client_tls_for_look_alikes() {
+        let mut staging_api_tls = staging_client_tls();
+        let _ = staging_api_tls.set_base_url(Some("https://staging.internal.com/api".to_string()));
+        let mut proxy_config = Proxy::default();
+        let _ = proxy_config.set_client_tls(vec![staging_client_tls(), staging_api_tls.clone()]);
+
+        for url in &[
+            "https://staging.internal.com.evil.net",
+            "https://staging.internal.com.evil.net/api",
+            "https://staging.internal.community/api",
+            "http://staging.internal.com/api",
+            "https://staging.internal.com:8443/api",
+        ] {
+            assert_eq!(proxy_config.client_tls_for(url), None, "{}", url);
+        }
+
+        assert_eq!(
+            proxy_config.client_tls_for("https://STAGING.internal.com:443/api"),
+            Some(&staging_api_tls)
+        );
+        assert_eq!(
+            proxy_config.client_tls_for("https://staging.internal.com/apiary"),
+            Some(&staging_client_tls())
+        );
+    }
+
+    #[test]
+    fn from_vars() {
+        let proxy_config = Proxy::from_vars(|name| match name {
+            "HTTPS_PROXY" => Some("http://a.secure.proxy.com".to_string()),
+            "NO_PROXY" => Some("localhost, .internal.com,".to_string()),
+            _ => None,
+        });
+        assert!(proxy_config.use_proxy());
+        assert!(proxy_config.check().is_ok());
+        assert_eq!(proxy_config.proxy_url(), &None);
+        assert_eq!(
+            proxy_config.https_proxy_url(),
+            &Some("http://a.secure.proxy.com".to_string())
+        );
+
+        // Only `https` upstreams go through `HTTPS_PROXY`.
+        let http_uri: Uri = "http://api.external.com/a".parse().expect("invalid uri");
+        let https_uri: Uri = "https://api.external.com/a".parse().expect("invalid uri");
+        assert_eq!(proxy_config.proxy_url_for(&http_uri, None), None);
+        assert_eq!(
+            proxy_config.proxy_url_for(&https_uri, None),
+            Some("http://a.secure.proxy.com")
+        );
+        assert_eq!(
+            proxy_config.no_proxy(),
+            &vec!["localhost".to_string(), ".internal.com".to_string()]
+        );
+
+        let disabled = Proxy::from_vars(|_| None);
+        assert_eq!(disabled, Proxy::default());
+    }
 }
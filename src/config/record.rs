@@ -0,0 +1,246 @@
+// Copyright (c) 2018 libdeadmock developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! `libdeadmock` record mode configuration
+use crate::error::Error;
+use clap::ArgMatches;
+use getset::{Getters, Setters};
+use serde_derive::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::path::PathBuf;
+
+/// The record mode configuration for deadmock.
+///
+/// When enabled, every proxied request/response pair is written out as a mapping file under
+/// `mappings_path` and a response body file under `files_path`.
+///
+/// # Example
+///
+/// ```
+/// # use libdeadmock::config;
+/// #
+/// # fn main() {
+///     // Record mode is disabled by default.
+///     let disabled = config::Record::default();
+///     assert!(!disabled.enabled());
+///
+///     // Record mode, matching on method and url.
+///     let mut record_config = config::Record::default();
+///     record_config.set_enabled(true);
+///     record_config.set_match_method(true);
+///     record_config.set_match_url(true);
+/// # }
+/// ```
+#[derive(Clone, Debug, Deserialize, Getters, Hash, Eq, PartialEq, Serialize, Setters)]
+#[serde(default)]
+pub struct Record {
+    /// Turn record mode on.
+    #[get = "pub"]
+    #[set = "pub"]
+    enabled: bool,
+    /// The path recorded mappings are written to.
+    #[get = "pub"]
+    #[set = "pub"]
+    mappings_path: PathBuf,
+    /// The path recorded response bodies are written to.
+    #[get = "pub"]
+    #[set = "pub"]
+    files_path: PathBuf,
+    /// Match recorded requests on the HTTP method.
+    #[get = "pub"]
+    #[set = "pub"]
+    match_method: bool,
+    /// Match recorded requests on the url.
+    #[get = "pub"]
+    #[set = "pub"]
+    match_url: bool,
+    /// The names of the request headers to match recorded requests on.
+    #[get = "pub"]
+    #[set = "pub"]
+    match_headers: Vec<String>,
+    /// Only record the first of a set of requests that would generate identical mappings.
+    #[get = "pub"]
+    #[set = "pub"]
+    dedupe: bool,
+}
+
+impl Default for Record {
+    /// Record mode is off, and matches recorded requests on the method and url.
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            mappings_path: PathBuf::from("mappings"),
+            files_path: PathBuf::from("files"),
+            match_method: true,
+            match_url: true,
+            match_headers: vec![],
+            dedupe: false,
+        }
+    }
+}
+
+impl Record {
+    /// Override the settings whose command line arguments are given, keeping the rest.
+    ///
+    /// The record paths are not read here, as `Runtime` sets them from its own directories.
+    crate fn with_matches<'a>(mut self, matches: &'a ArgMatches<'a>) -> Self {
+        if matches.is_present("record") {
+            self.enabled = true;
+        }
+        if let Some(parts) = matches.values_of("record-match") {
+            let parts: Vec<&str> = parts.collect();
+            self.match_method = parts.contains(&"method");
+            self.match_url = parts.contains(&"url");
+        }
+        if let Some(values) = matches.values_of("record-header") {
+            self.match_headers = values.map(|s| s.to_string()).collect();
+        }
+        if matches.is_present("record-dedupe") {
+            self.dedupe = true;
+        }
+        self
+    }
+}
+
+impl<'a> TryFrom<&'a ArgMatches<'a>> for Record {
+    type Error = Error;
+
+    fn try_from(matches: &'a ArgMatches<'a>) -> Result<Self, Error> {
+        let mut record = Self::default();
+        if let Some(mappings_path) = matches.value_of("mappings_path") {
+            record.mappings_path = PathBuf::from(mappings_path).join("mappings");
+        }
+        if let Some(files_path) = matches.value_of("files_path") {
+            record.files_path = PathBuf::from(files_path).join("files");
+        }
+        Ok(record.with_matches(matches))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Record;
+    use clap::{App, Arg};
+    use std::convert::TryFrom;
+    use std::path::PathBuf;
+
+    fn test_cli() -> App<'static, 'static> {
+        App::new("record-config-test")
+            .version("1")
+            .author("Yoda")
+            .about("command line for record config testing")
+            .arg(
+                Arg::with_name("mappings_path")
+                    .short("m")
+                    .long("mappings_path")
+                    .takes_value(true)
+                    .value_name("MAPPINGS_PATH"),
+            )
+            .arg(
+                Arg::with_name("files_path")
+                    .short("f")
+                    .long("files_path")
+                    .takes_value(true)
+                    .value_name("FILES_PATH"),
+            )
+            .arg(
+                Arg::with_name("record")
+                    .short("r")
+                    .long("record")
+                    .help("Record proxied traffic"),
+            )
+            .arg(
+                Arg::with_name("record-match")
+                    .long("record-match")
+                    .takes_value(true)
+                    .multiple(true)
+                    .use_delimiter(true)
+                    .possible_values(&["method", "url"])
+                    .value_name("PART")
+                    .help("The request parts to match recorded requests on"),
+            )
+            .arg(
+                Arg::with_name("record-header")
+                    .long("record-header")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+                    .value_name("HEADER")
+                    .help("A request header to match recorded requests on"),
+            )
+            .arg(
+                Arg::with_name("record-dedupe")
+                    .long("record-dedupe")
+                    .help("Only record the first of a set of identical requests"),
+            )
+    }
+
+    #[test]
+    fn default_is_disabled() {
+        let record_config = Record::default();
+        assert!(!record_config.enabled());
+    }
+
+    #[test]
+    fn from_no_args() {
+        let matches = test_cli().get_matches_from(vec!["test-cli"]);
+        match Record::try_from(&matches) {
+            Ok(record_config) => {
+                assert!(!record_config.enabled());
+                assert!(record_config.match_method());
+                assert!(record_config.match_url());
+                assert!(record_config.match_headers().is_empty());
+                assert!(!record_config.dedupe());
+                assert_eq!(record_config.mappings_path(), &PathBuf::from("mappings"));
+                assert_eq!(record_config.files_path(), &PathBuf::from("files"));
+            }
+            Err(_) => assert!(false, "Not expected to error!"),
+        }
+    }
+
+    #[test]
+    fn from_all_args() {
+        let arg_vec = vec![
+            "test-cli",
+            "-r",
+            "-m",
+            "tests",
+            "-f",
+            "tests",
+            "--record-match",
+            "url",
+            "--record-header",
+            "X-Correlation-Id",
+            "--record-header",
+            "Accept",
+            "--record-dedupe",
+        ];
+        let matches = test_cli().get_matches_from(arg_vec);
+        match Record::try_from(&matches) {
+            Ok(record_config) => {
+                assert!(record_config.enabled());
+                assert!(!record_config.match_method());
+                assert!(record_config.match_url());
+                assert_eq!(
+                    record_config.match_headers(),
+                    &vec!["X-Correlation-Id".to_string(), "Accept".to_string()]
+                );
+                assert!(record_config.dedupe());
+                assert_eq!(
+                    record_config.mappings_path(),
+                    &PathBuf::from("tests").join("mappings")
+                );
+                assert_eq!(
+                    record_config.files_path(),
+                    &PathBuf::from("tests").join("files")
+                );
+            }
+            Err(_) => assert!(false, "Not expected to error!"),
+        }
+    }
+}
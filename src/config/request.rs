@@ -7,12 +7,16 @@
 // modified, or distributed except according to those terms.
 
 //! HTTP request matching configuration
-use crate::config::{Header, HeaderPattern};
+use crate::config::{Header, HeaderPattern, Pattern, UrlOptions};
 use getset::Getters;
+use serde::de::{Deserialize, Deserializer};
 use serde_derive::{Deserialize, Serialize};
 
 /// HTTP request matching configuration.
-#[derive(Clone, Debug, Default, Deserialize, Getters, Hash, Eq, PartialEq, Serialize)]
+///
+/// The url options are applied to the url pattern as the configuration is built or
+/// deserialized.
+#[derive(Clone, Debug, Default, Getters, Hash, Eq, PartialEq, Serialize)]
 pub struct Request {
     /// The HTTP request method to match.
     #[get = "pub"]
@@ -21,7 +25,7 @@ pub struct Request {
     /// The HTTP request method pattern to match
     #[get = "pub"]
     #[serde(skip_serializing_if = "Option::is_none")]
-    method_pattern: Option<String>,
+    method_pattern: Option<Pattern>,
     /// The url to exact match.
     #[get = "pub"]
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -29,7 +33,11 @@ pub struct Request {
     /// The url to pattern match (regex).
     #[get = "pub"]
     #[serde(skip_serializing_if = "Option::is_none")]
-    url_pattern: Option<String>,
+    url_pattern: Option<Pattern>,
+    /// How the request path is compared with `url` and `url_pattern`.
+    #[get = "pub"]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url_options: Option<UrlOptions>,
     /// The HTTP headers to match (exact).
     #[serde(default)]
     #[serde(skip_serializing_if = "Vec::is_empty")]
@@ -50,6 +58,187 @@ pub struct Request {
     header_pattern: Option<HeaderPattern>,
 }
 
+impl Request {
+    /// Match `GET` requests to the given url.
+    ///
+    /// # Example
+    /// ```
+    /// # use libdeadmock::config;
+    /// #
+    /// # fn main() {
+    ///     let request = config::Request::get("/users").with_header("Accept", "application/json");
+    ///     assert_eq!(request.method(), &Some("GET".to_string()));
+    ///     assert_eq!(request.url(), &Some("/users".to_string()));
+    ///     assert_eq!(request.headers().len(), 1);
+    /// # }
+    /// ```
+    pub fn get<U: Into<String>>(url: U) -> Self {
+        Self::with_method("GET", url)
+    }
+
+    /// Match `POST` requests to the given url.
+    pub fn post<U: Into<String>>(url: U) -> Self {
+        Self::with_method("POST", url)
+    }
+
+    /// Match `PUT` requests to the given url.
+    pub fn put<U: Into<String>>(url: U) -> Self {
+        Self::with_method("PUT", url)
+    }
+
+    /// Match `PATCH` requests to the given url.
+    pub fn patch<U: Into<String>>(url: U) -> Self {
+        Self::with_method("PATCH", url)
+    }
+
+    /// Match `DELETE` requests to the given url.
+    pub fn delete<U: Into<String>>(url: U) -> Self {
+        Self::with_method("DELETE", url)
+    }
+
+    /// Match requests with the given method to the given url.
+    pub fn with_method<M, U>(method: M, url: U) -> Self
+    where
+        M: Into<String>,
+        U: Into<String>,
+    {
+        Self {
+            method: Some(method.into()),
+            url: Some(url.into()),
+            ..Self::default()
+        }
+    }
+
+    /// Also match the given header (exact).
+    pub fn with_header<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.headers.push(Header::new(key, value));
+        self
+    }
+
+    /// Also match the url against the given pattern (regex).
+    pub fn with_url_pattern(mut self, url_pattern: Pattern) -> Self {
+        self.url_pattern = Some(url_pattern);
+        self.apply_url_options();
+        self
+    }
+
+    /// Compare the request path with the url and url pattern using the given options.
+    pub fn with_url_options(mut self, url_options: UrlOptions) -> Self {
+        self.url_options = Some(url_options);
+        self.apply_url_options();
+        self
+    }
+
+    /// Compile the url pattern to match ignoring case, or not, as the url options ask.
+    ///
+    /// This is done once, by the builder methods and as the configuration is deserialized, so
+    /// the url matcher never compiles a pattern per request.  A pattern that doesn't compile is
+    /// reported by `patterns`.
+    fn apply_url_options(&mut self) {
+        let ignore_case = self
+            .url_options
+            .map_or(false, |url_options| *url_options.case_insensitive());
+        if let Some(url_pattern) = &self.url_pattern {
+            if url_pattern.is_ignore_case() != ignore_case {
+                self.url_pattern = Some(url_pattern.with_ignore_case(ignore_case));
+            }
+        }
+    }
+
+    /// The patterns to match, with the name of the field each is from, so those that didn't
+    /// compile can be reported.
+    crate fn patterns(&self) -> Vec<(&'static str, &Pattern)> {
+        let mut patterns = vec![];
+        if let Some(method_pattern) = &self.method_pattern {
+            patterns.push(("method_pattern", method_pattern));
+        }
+        if let Some(url_pattern) = &self.url_pattern {
+            patterns.push(("url_pattern", url_pattern));
+        }
+        for header_pattern in self.headers_pattern.iter().chain(&self.header_pattern) {
+            if let Ok(key) = header_pattern.key().right_ref() {
+                patterns.push(("header pattern key", key));
+            }
+            if let Ok(value) = header_pattern.value().right_ref() {
+                patterns.push(("header pattern value", value));
+            }
+        }
+        patterns
+    }
+
+    /// The number of request matching criteria configured.
+    ///
+    /// Between mappings of equal priority, the more specific mapping wins.
+    pub fn specificity(&self) -> usize {
+        [
+            self.method.is_some(),
+            self.method_pattern.is_some(),
+            self.url.is_some(),
+            self.url_pattern.is_some(),
+            self.header.is_some(),
+            self.header_pattern.is_some(),
+        ]
+        .iter()
+        .filter(|configured| **configured)
+        .count()
+            + self.headers.len()
+            + self.headers_pattern.len()
+    }
+
+    /// Create a request matching configuration for a recorded request.
+    crate fn recorded(
+        method: Option<String>,
+        url: Option<String>,
+        headers: Vec<Header>,
+    ) -> Self {
+        Self {
+            method,
+            url,
+            headers,
+            ..Self::default()
+        }
+    }
+}
+
+/// The deserialized form of `Request`, before the url options are applied.
+#[derive(Deserialize)]
+struct Unprepared {
+    method: Option<String>,
+    method_pattern: Option<Pattern>,
+    url: Option<String>,
+    url_pattern: Option<Pattern>,
+    url_options: Option<UrlOptions>,
+    #[serde(default)]
+    headers: Vec<Header>,
+    #[serde(default)]
+    headers_pattern: Vec<HeaderPattern>,
+    header: Option<Header>,
+    header_pattern: Option<HeaderPattern>,
+}
+
+impl<'de> Deserialize<'de> for Request {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let unprepared = Unprepared::deserialize(deserializer)?;
+        let mut request = Self {
+            method: unprepared.method,
+            method_pattern: unprepared.method_pattern,
+            url: unprepared.url,
+            url_pattern: unprepared.url_pattern,
+            url_options: unprepared.url_options,
+            headers: unprepared.headers,
+            headers_pattern: unprepared.headers_pattern,
+            header: unprepared.header,
+            header_pattern: unprepared.header_pattern,
+        };
+        request.apply_url_options();
+        Ok(request)
+    }
+}
+
 #[cfg(test)]
 crate mod test {
     use super::Request;
@@ -57,6 +246,8 @@ crate mod test {
         accept_star_pattern, content_type_header, content_type_header_pattern,
         content_type_star_pattern,
     };
+    use crate::config::pattern::test::pattern;
+    use crate::config::UrlOptions;
 
     const EMPTY_REQUEST: &str = "{}";
     const PARTIAL_REQUEST: &str = r#"{"method":"GET","url":"http://a.url.com"}"#;
@@ -104,8 +295,8 @@ right = "^application/.*"
 
     crate fn full_request() -> Request {
         let mut request = partial_request();
-        request.method_pattern = Some("P.*".to_string());
-        request.url_pattern = Some(".*jasonozias.*".to_string());
+        request.method_pattern = Some(pattern("P.*"));
+        request.url_pattern = Some(pattern(".*jasonozias.*"));
         request.headers = vec![content_type_header()];
         request.headers_pattern = vec![accept_star_pattern(), content_type_star_pattern()];
         request.header = Some(content_type_header());
@@ -207,4 +398,54 @@ right = "^application/.*"
             "Expected the deserialization to fail!"
         );
     }
+
+    #[test]
+    fn specificity() {
+        assert_eq!(Request::default().specificity(), 0);
+        assert_eq!(partial_request().specificity(), 2);
+        assert_eq!(full_request().specificity(), 9);
+    }
+
+    #[test]
+    fn builder() {
+        let request = Request::post("/users")
+            .with_header("Content-Type", "application/json")
+            .with_header("Accept", "application/json")
+            .with_url_pattern(pattern("^/users$"));
+        assert_eq!(request.method(), &Some("POST".to_string()));
+        assert_eq!(request.url(), &Some("/users".to_string()));
+        assert_eq!(request.url_pattern(), &Some(pattern("^/users$")));
+        assert_eq!(request.headers().len(), 2);
+        assert_eq!(
+            Request::with_method("OPTIONS", "/").method(),
+            &Some("OPTIONS".to_string())
+        );
+    }
+
+    #[test]
+    fn url_options() {
+        let ignores_case = |request: &Request| {
+            request
+                .url_pattern()
+                .as_ref()
+                .map_or(false, |url_pattern| url_pattern.is_match("/USERS"))
+        };
+        let mut case_insensitive = UrlOptions::default();
+        let _ = case_insensitive.set_case_insensitive(true);
+
+        let built = Request::default()
+            .with_url_pattern(pattern("^/users$"))
+            .with_url_options(case_insensitive);
+        assert!(ignores_case(&built));
+        assert!(!ignores_case(
+            &built.with_url_options(UrlOptions::default())
+        ));
+
+        match serde_json::from_str::<Request>(
+            r#"{"url_pattern":"^/users$","url_options":{"case_insensitive":true}}"#,
+        ) {
+            Ok(deserialized) => assert!(ignores_case(&deserialized)),
+            Err(e) => assert!(false, e.to_string()),
+        }
+    }
 }
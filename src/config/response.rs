@@ -27,6 +27,10 @@ pub struct Response {
     #[serde(skip_serializing_if = "Option::is_none")]
     #[get = "pub"]
     body_file_name: Option<String>,
+    /// An inline http response body.  This takes precedence over `body_file_name`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    body: Option<String>,
     /// The base url of the proxy you wish to generate the response from.
     #[serde(skip_serializing_if = "Option::is_none")]
     #[get = "pub"]
@@ -36,6 +40,110 @@ pub struct Response {
     #[serde(skip_serializing_if = "Vec::is_empty")]
     #[get = "pub"]
     additional_proxy_request_headers: Vec<Header>,
+    /// Override the proxy configuration for this mapping.  `false` bypasses the proxy, and `true`
+    /// uses the proxy even if the upstream host is in the `no_proxy` list.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    use_proxy: Option<bool>,
+    /// Override the upstream connect timeout, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    connect_timeout_ms: Option<u64>,
+    /// Override the total timeout for one upstream request attempt, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    timeout_ms: Option<u64>,
+    /// Override the number of times a failed upstream request is retried.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    retries: Option<u32>,
+    /// Override the delay before the first retry, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    retry_backoff_ms: Option<u64>,
+    /// Override whether failed requests with a non-idempotent method are retried.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    retry_non_idempotent: Option<bool>,
+}
+
+impl Response {
+    /// Create a response configuration with the given status code.
+    ///
+    /// # Example
+    /// ```
+    /// # use libdeadmock::config;
+    /// # use serde_json::json;
+    /// #
+    /// # fn main() {
+    ///     let response = config::Response::with_status(201)
+    ///         .with_header("X-Request-Id", "1")
+    ///         .with_json_body(&json!({ "id": 1 }));
+    ///     assert_eq!(response.status(), &Some(201));
+    ///     assert_eq!(response.body(), &Some(r#"{"id":1}"#.to_string()));
+    /// # }
+    /// ```
+    pub fn with_status(status: u16) -> Self {
+        Self {
+            status: Some(status),
+            ..Self::default()
+        }
+    }
+
+    /// Create a response configuration that proxies to the given base url.
+    pub fn proxy(proxy_base_url: String) -> Self {
+        Self {
+            proxy_base_url: Some(proxy_base_url),
+            ..Self::default()
+        }
+    }
+
+    /// Add a header to this response.
+    pub fn with_header<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.headers.push(Header::new(key, value));
+        self
+    }
+
+    /// Use the given inline body for this response.
+    pub fn with_body<B: Into<String>>(mut self, body: B) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// Use the given JSON as the inline body for this response, and set the `Content-Type` to
+    /// `application/json` if it is not set already.
+    pub fn with_json_body(mut self, body: &serde_json::Value) -> Self {
+        if !self
+            .headers
+            .iter()
+            .any(|header| header.key().eq_ignore_ascii_case("content-type"))
+        {
+            self.headers
+                .push(Header::new("Content-Type", "application/json"));
+        }
+        self.body = Some(body.to_string());
+        self
+    }
+
+    /// Use the given file, under the files path, as the body for this response.
+    pub fn with_body_file<F: Into<String>>(mut self, body_file_name: F) -> Self {
+        self.body_file_name = Some(body_file_name.into());
+        self
+    }
+
+    /// Create a response configuration for a recorded response.
+    crate fn recorded(status: u16, headers: Vec<Header>, body_file_name: String) -> Self {
+        Self {
+            status: Some(status),
+            headers,
+            body_file_name: Some(body_file_name),
+            ..Self::default()
+        }
+    }
 }
 
 #[cfg(test)]
@@ -167,4 +275,24 @@ value = "application/json"
             "Expected the deserialization to fail!"
         );
     }
+
+    #[test]
+    fn builder() {
+        let response = Response::with_status(200)
+            .with_header("Content-Type", "text/plain")
+            .with_json_body(&serde_json::json!({ "a": [1, 2] }));
+        assert_eq!(response.status(), &Some(200));
+        assert_eq!(response.headers().len(), 1);
+        assert_eq!(response.headers()[0].value(), "text/plain");
+        assert_eq!(response.body(), &Some(r#"{"a":[1,2]}"#.to_string()));
+
+        let response = Response::with_status(404).with_json_body(&serde_json::json!(null));
+        assert_eq!(response.headers()[0].value(), "application/json");
+
+        let response = Response::with_status(200)
+            .with_body_file("test.json")
+            .with_body("inline");
+        assert_eq!(response.body_file_name(), &Some("test.json".to_string()));
+        assert_eq!(response.body(), &Some("inline".to_string()));
+    }
 }
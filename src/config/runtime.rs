@@ -7,25 +7,131 @@
 // modified, or distributed except according to those terms.
 
 //! Runtime environment configuration
-use getset::Getters;
+use crate::config::{Admin, Proxy, Record, ServerTls, Upstream};
+use crate::error::Error::{self, InvalidRuntimeConfig};
+use clap::ArgMatches;
+use getset::{Getters, Setters};
 use serde_derive::{Deserialize, Serialize};
 use std::env;
-use tomlenv::Environment;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use tomlenv::{Environment, Environments};
 
 const ENV: &str = "env";
+const DEFAULT_IP: &str = "127.0.0.1";
+const DEFAULT_PORT: u32 = 32276;
+const DEFAULT_TLS_PORT: u16 = 32277;
 
 /// The runtime environment configuration for deadmock.
-#[derive(Clone, Debug, Default, Deserialize, Getters, Hash, Eq, PartialEq, Serialize)]
+///
+/// This is loaded per environment from TOML with [`tomlenv`](tomlenv), then the `DEADMOCK_*`
+/// environment variables and the command line arguments are layered on top.
+///
+/// # Example
+///
+/// ```
+/// # use libdeadmock::config;
+/// # use std::io::Cursor;
+/// #
+/// # fn main() {
+///     let toml = r#"[envs.local]
+/// port = 8080
+/// path = "tests"
+///
+/// [envs.local.upstream]
+/// timeout_ms = 5000
+///
+/// [envs.prod]
+/// ip = "0.0.0.0"
+/// port = 80
+/// "#;
+///     let runtime = config::Runtime::from_reader(&mut Cursor::new(toml)).unwrap();
+///     assert_eq!(runtime.port(), &Some(8080));
+///     assert_eq!(runtime.socket_addr().unwrap().to_string(), "127.0.0.1:8080");
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, Getters, Hash, Eq, PartialEq, Serialize, Setters)]
 pub struct Runtime {
     /// The IP address to listen on.
     #[get = "pub"]
+    #[set = "pub"]
     ip: Option<String>,
     /// The port to listen on.
     #[get = "pub"]
+    #[set = "pub"]
     port: Option<u32>,
     /// The path to the mappings and templates
     #[get = "pub"]
+    #[set = "pub"]
     path: Option<String>,
+    /// The path to the `mappings` directory, if it isn't under `path`.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    #[set = "pub"]
+    mappings_path: Option<String>,
+    /// The path to the `files` directory, if it isn't under `path`.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    #[set = "pub"]
+    files_path: Option<String>,
+    /// The stdout log level, i.e. `info` or `trace`.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    #[set = "pub"]
+    log_level: Option<String>,
+    /// The fallback proxy base url for requests that match no mapping.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    #[set = "pub"]
+    fallback: Option<String>,
+    /// Watch the mappings and files for changes.
+    #[serde(default)]
+    #[get = "pub"]
+    #[set = "pub"]
+    watch: bool,
+    /// Include near-miss diagnostics in the 404 response for unmatched requests.
+    #[serde(default)]
+    #[get = "pub"]
+    #[set = "pub"]
+    diagnostics: bool,
+    /// The largest request body that is accepted, in bytes.  Larger requests get a 413.
+    #[serde(default)]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    #[set = "pub"]
+    max_body_size: Option<usize>,
+    /// The proxy configuration.
+    #[serde(default)]
+    #[get = "pub"]
+    #[set = "pub"]
+    proxy: Proxy,
+    /// The upstream timeout and retry configuration.
+    #[serde(default)]
+    #[get = "pub"]
+    #[set = "pub"]
+    upstream: Upstream,
+    /// The record mode configuration.  The recorded mappings and files are written to the
+    /// `mappings` and `files` directories, so they are loaded on the next start or reload.
+    #[serde(default)]
+    #[get = "pub"]
+    #[set = "pub"]
+    record: Record,
+    /// The TLS listener configuration.
+    #[serde(default)]
+    #[get = "pub"]
+    #[set = "pub"]
+    tls: ServerTls,
+    /// The admin API configuration.
+    #[serde(default)]
+    #[get = "pub"]
+    #[set = "pub"]
+    admin: Admin,
 }
 
 impl Runtime {
@@ -47,20 +153,233 @@ impl Runtime {
             env_str
         })
     }
+
+    /// Load the configuration for the current environment (see `env`) from per-environment TOML.
+    pub fn from_reader<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        let _ = Self::env();
+        let envs: Environments<Environment, Self> = Environments::from_reader(reader)?;
+        let runtime = envs.current()?.clone();
+        runtime.check()?;
+        Ok(runtime)
+    }
+
+    /// Check the sections that can't be checked while they are deserialized, i.e. that the proxy
+    /// has a url if it is on.
+    pub fn check(&self) -> Result<(), Error> {
+        self.proxy.check()?;
+        self.admin.check()
+    }
+
+    /// Load the configuration for the current environment from the given TOML file.
+    pub fn from_path(path: &Path) -> Result<Self, Error> {
+        let mut reader = BufReader::new(File::open(path)?);
+        Self::from_reader(&mut reader)
+    }
+
+    /// Layer the `DEADMOCK_IP`, `DEADMOCK_PORT`, `DEADMOCK_PATH`, `DEADMOCK_MAPPINGS_PATH`,
+    /// `DEADMOCK_FILES_PATH`, `DEADMOCK_LOG_LEVEL`, and `DEADMOCK_FALLBACK` environment
+    /// variables, and the proxy environment variables, on top of this configuration.
+    pub fn with_env(self) -> Result<Self, Error> {
+        let mut runtime = self.with_vars(|name| env::var(name).ok())?;
+        let env_proxy = Proxy::from_env();
+
+        if runtime.proxy.proxy_url().is_none()
+            && runtime.proxy.https_proxy_url().is_none()
+            && *env_proxy.use_proxy()
+        {
+            let _ = runtime.proxy.set_use_proxy(true);
+            let _ = runtime.proxy.set_proxy_url(env_proxy.proxy_url().clone());
+            let _ = runtime
+                .proxy
+                .set_https_proxy_url(env_proxy.https_proxy_url().clone());
+        }
+        let mut no_proxy = runtime.proxy.no_proxy().clone();
+        no_proxy.extend(env_proxy.no_proxy().iter().cloned());
+        let _ = runtime.proxy.set_no_proxy(no_proxy);
+
+        Ok(runtime)
+    }
+
+    fn with_vars<F>(mut self, var: F) -> Result<Self, Error>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        if let Some(port) = var("DEADMOCK_PORT") {
+            self.port = Some(port.parse().map_err(|_| InvalidRuntimeConfig)?);
+        }
+        self.ip = var("DEADMOCK_IP").or(self.ip);
+        self.path = var("DEADMOCK_PATH").or(self.path);
+        self.mappings_path = var("DEADMOCK_MAPPINGS_PATH").or(self.mappings_path);
+        self.files_path = var("DEADMOCK_FILES_PATH").or(self.files_path);
+        self.log_level = var("DEADMOCK_LOG_LEVEL").or(self.log_level);
+        self.fallback = var("DEADMOCK_FALLBACK").or(self.fallback);
+        Ok(self)
+    }
+
+    /// Layer the command line arguments on top of this configuration.
+    ///
+    /// Each argument overrides only its own setting, so the rest of the proxy, upstream, record,
+    /// TLS, and admin sections is kept.
+    pub fn with_matches<'a>(mut self, matches: &'a ArgMatches<'a>) -> Result<Self, Error> {
+        if let Some(port) = matches.value_of("port") {
+            self.port = Some(port.parse().map_err(|_| InvalidRuntimeConfig)?);
+        }
+        if let Some(ip) = matches.value_of("ip") {
+            self.ip = Some(ip.to_string());
+        }
+        if let Some(mappings_path) = matches.value_of("mappings_path") {
+            self.mappings_path = Some(mappings_path.to_string());
+        }
+        if let Some(files_path) = matches.value_of("files_path") {
+            self.files_path = Some(files_path.to_string());
+        }
+        if let Some(fallback) = matches.value_of("fallback") {
+            self.fallback = Some(fallback.to_string());
+        }
+        if let Some(max_body_size) = matches.value_of("max-body-size") {
+            self.max_body_size = Some(max_body_size.parse().map_err(|_| InvalidRuntimeConfig)?);
+        }
+        self.log_level = match matches.occurrences_of("v") {
+            0 => self.log_level,
+            1 => Some("info".to_string()),
+            2 => Some("debug".to_string()),
+            3 | _ => Some("trace".to_string()),
+        };
+        self.watch = self.watch || matches.is_present("watch");
+        self.diagnostics = self.diagnostics || matches.is_present("diagnostics");
+
+        // Only the settings with a command line argument given are overridden, so the rest of
+        // each section from the file is kept.
+        self.proxy = self.proxy.with_matches(matches)?;
+        self.upstream = self.upstream.with_matches(matches)?;
+        self.record = self.record.with_matches(matches);
+        self.tls = self.tls.with_matches(matches)?;
+        self.admin = self.admin.with_matches(matches)?;
+
+        Ok(self)
+    }
+
+    /// The address for the plain listener.
+    pub fn socket_addr(&self) -> Result<SocketAddr, Error> {
+        to_socket_addr(
+            self.ip.as_ref().map_or(DEFAULT_IP, String::as_str),
+            self.port.unwrap_or(DEFAULT_PORT),
+        )
+    }
+
+    /// The address for the TLS listener.
+    pub fn tls_socket_addr(&self) -> Result<SocketAddr, Error> {
+        to_socket_addr(
+            self.ip.as_ref().map_or(DEFAULT_IP, String::as_str),
+            u32::from(self.tls.port().unwrap_or(DEFAULT_TLS_PORT)),
+        )
+    }
+
+    /// The `mappings` directory.
+    pub fn mappings_dir(&self) -> PathBuf {
+        sub_dir(
+            self.mappings_path.as_ref().or_else(|| self.path.as_ref()),
+            "mappings",
+        )
+    }
+
+    /// The `files` directory.
+    pub fn files_dir(&self) -> PathBuf {
+        sub_dir(
+            self.files_path.as_ref().or_else(|| self.path.as_ref()),
+            "files",
+        )
+    }
+}
+
+fn to_socket_addr(ip: &str, port: u32) -> Result<SocketAddr, Error> {
+    format!("{}:{}", ip, port)
+        .parse()
+        .map_err(|_| InvalidRuntimeConfig)
+}
+
+fn sub_dir(root: Option<&String>, name: &str) -> PathBuf {
+    root.map_or_else(
+        || PathBuf::from(name),
+        |root| PathBuf::from(root).join(name),
+    )
 }
 
 #[cfg(test)]
 mod test {
     use super::{Runtime, ENV};
+    use clap::{App, Arg};
     use std::env;
+    use std::path::PathBuf;
     use tomlenv::Environment;
 
+    const RUNTIME_TOML: &str = r#"port = 8080
+path = "tests"
+watch = true
+diagnostics = true
+max_body_size = 1024
+
+[proxy]
+use_proxy = true
+proxy_url = "http://a.proxy.com"
+
+[[proxy.client_tls]]
+base_url = "https://staging.internal.com"
+ca_bundle = "certs/internal-ca.pem"
+
+[upstream]
+timeout_ms = 5000
+retries = 2
+
+[record]
+enabled = true
+
+[tls]
+enabled = true
+self_signed = true
+port = 8443
+
+[admin]
+enabled = true
+"#;
+
+    fn test_cli() -> App<'static, 'static> {
+        App::new("runtime-config-test")
+            .version("1")
+            .author("Yoda")
+            .about("command line for runtime config testing")
+            .arg(Arg::with_name("ip").long("ip").takes_value(true))
+            .arg(Arg::with_name("port").long("port").takes_value(true))
+            .arg(
+                Arg::with_name("mappings_path")
+                    .short("m")
+                    .long("mappings_path")
+                    .takes_value(true),
+            )
+            .arg(Arg::with_name("timeout").long("timeout").takes_value(true))
+            .arg(
+                Arg::with_name("no-proxy")
+                    .long("no-proxy")
+                    .takes_value(true)
+                    .multiple(true),
+            )
+            .arg(Arg::with_name("upstream-insecure").long("upstream-insecure"))
+            .arg(
+                Arg::with_name("tls-port")
+                    .long("tls-port")
+                    .takes_value(true),
+            )
+            .arg(Arg::with_name("record-dedupe").long("record-dedupe"))
+            .arg(Arg::with_name("v").short("v").multiple(true))
+    }
+
     fn validate_env(currenv: &str) {
         env::set_var(ENV, &currenv);
         assert_eq!(Runtime::env(), currenv);
         assert!(env::var(ENV).is_ok());
         env::remove_var(ENV);
     }
+
     #[test]
     fn local_env_when_not_set() {
         let local_env = Environment::Local.to_string();
@@ -75,4 +394,122 @@ mod test {
         validate_env(&Environment::Stage.to_string());
         validate_env(&Environment::Prod.to_string());
     }
+
+    #[test]
+    fn layered_runtime() {
+        // The environment section of the file, as `Runtime::from_reader` selects it.
+        let runtime = match toml::from_str::<Runtime>(RUNTIME_TOML) {
+            Ok(runtime) => runtime,
+            Err(e) => return assert!(false, e.to_string()),
+        };
+
+        assert_eq!(runtime.port(), &Some(8080));
+        assert!(runtime.watch());
+        assert!(runtime.diagnostics());
+        assert_eq!(runtime.max_body_size(), &Some(1024));
+        assert!(runtime.proxy().use_proxy());
+        assert_eq!(runtime.upstream().timeout_ms(), &5000);
+        assert_eq!(runtime.upstream().retry_backoff_ms(), &100);
+        assert!(runtime.tls().self_signed());
+        assert!(runtime.admin().enabled());
+        assert_eq!(runtime.admin().path(), "/__admin");
+        assert!(runtime.check().is_ok());
+        assert_eq!(
+            runtime.mappings_dir(),
+            PathBuf::from("tests").join("mappings")
+        );
+        assert_eq!(runtime.files_dir(), PathBuf::from("tests").join("files"));
+
+        let runtime = match runtime.with_vars(|name| match name {
+            "DEADMOCK_IP" => Some("0.0.0.0".to_string()),
+            "DEADMOCK_PORT" => Some("9090".to_string()),
+            _ => None,
+        }) {
+            Ok(runtime) => runtime,
+            Err(e) => return assert!(false, e.to_string()),
+        };
+        assert_eq!(runtime.ip(), &Some("0.0.0.0".to_string()));
+        assert_eq!(runtime.port(), &Some(9090));
+        assert!(runtime
+            .clone()
+            .with_vars(|name| if name == "DEADMOCK_PORT" {
+                Some("abc".to_string())
+            } else {
+                None
+            })
+            .is_err());
+
+        let arg_vec = vec![
+            "test-cli",
+            "--port",
+            "7070",
+            "-m",
+            "other",
+            "--timeout",
+            "250",
+            "--no-proxy",
+            "localhost",
+            "--upstream-insecure",
+            "--tls-port",
+            "9443",
+            "--record-dedupe",
+            "-vv",
+        ];
+        let matches = test_cli().get_matches_from(arg_vec);
+        match runtime.with_matches(&matches) {
+            Ok(runtime) => {
+                assert_eq!(runtime.socket_addr().ok(), "0.0.0.0:7070".parse().ok());
+                assert_eq!(runtime.tls_socket_addr().ok(), "0.0.0.0:9443".parse().ok());
+                assert_eq!(runtime.log_level(), &Some("debug".to_string()));
+
+                // Each argument overrides its own setting, and the rest of the file is kept.
+                assert_eq!(runtime.upstream().timeout_ms(), &250);
+                assert_eq!(runtime.upstream().retries(), &2);
+                assert!(runtime.proxy().use_proxy());
+                assert_eq!(
+                    runtime.proxy().proxy_url(),
+                    &Some("http://a.proxy.com".to_string())
+                );
+                assert_eq!(runtime.proxy().no_proxy(), &vec!["localhost".to_string()]);
+                let client_tls = runtime.proxy().client_tls();
+                assert_eq!(client_tls.len(), 2);
+                assert_eq!(
+                    client_tls[0].base_url(),
+                    &Some("https://staging.internal.com".to_string())
+                );
+                assert!(!client_tls[0].accept_invalid_certs());
+                assert!(client_tls[1].base_url().is_none());
+                assert!(client_tls[1].accept_invalid_certs());
+                assert!(runtime.tls().enabled());
+                assert!(runtime.tls().self_signed());
+                assert!(runtime.record().enabled());
+                assert!(runtime.record().dedupe());
+                assert!(runtime.admin().enabled());
+                assert_eq!(
+                    runtime.mappings_dir(),
+                    PathBuf::from("other").join("mappings")
+                );
+                assert_eq!(runtime.files_dir(), PathBuf::from("tests").join("files"));
+            }
+            Err(e) => assert!(false, e.to_string()),
+        }
+    }
+
+    #[test]
+    fn check_sections() {
+        for toml in &["[proxy]\nuse_proxy = true", "[admin]\npath = \"admin\""] {
+            match toml::from_str::<Runtime>(toml) {
+                Ok(runtime) => assert!(runtime.check().is_err(), "{}", toml),
+                Err(e) => assert!(false, e.to_string()),
+            }
+        }
+    }
+
+    #[test]
+    fn default_socket_addr() {
+        assert_eq!(
+            Runtime::default().socket_addr().ok(),
+            "127.0.0.1:32276".parse().ok()
+        );
+    }
 }
@@ -0,0 +1,99 @@
+// Copyright (c) 2018 libdeadmock developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! `libdeadmock` call count expectations
+use serde_derive::{Deserialize, Serialize};
+use std::fmt;
+
+/// How many times a mapping is expected to be matched.
+///
+/// # Example
+///
+/// In TOML, `expect = { exactly = 1 }`, `expect = { at_least = 2 }`, or `expect = "never"`.
+///
+/// ```
+/// # use libdeadmock::config::Times;
+/// #
+/// # fn main() {
+///     assert!(Times::AtLeast(2).is_met(3));
+///     assert!(!Times::Never.is_met(1));
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Times {
+    /// Exactly the given number of times.
+    Exactly(u64),
+    /// At least the given number of times.
+    AtLeast(u64),
+    /// At most the given number of times.
+    AtMost(u64),
+    /// Never.
+    Never,
+}
+
+impl Times {
+    /// Is the expectation met by the given number of matches?
+    pub fn is_met(self, hits: u64) -> bool {
+        match self {
+            Times::Exactly(expected) => hits == expected,
+            Times::AtLeast(expected) => hits >= expected,
+            Times::AtMost(expected) => hits <= expected,
+            Times::Never => hits == 0,
+        }
+    }
+}
+
+impl fmt::Display for Times {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Times::Exactly(expected) => write!(f, "exactly {}", expected),
+            Times::AtLeast(expected) => write!(f, "at least {}", expected),
+            Times::AtMost(expected) => write!(f, "at most {}", expected),
+            Times::Never => write!(f, "never"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Times;
+    use serde_derive::Deserialize;
+
+    #[derive(Deserialize)]
+    struct Expect {
+        expect: Times,
+    }
+
+    #[test]
+    fn is_met() {
+        assert!(Times::Exactly(1).is_met(1));
+        assert!(!Times::Exactly(1).is_met(2));
+        assert!(Times::AtLeast(2).is_met(2));
+        assert!(!Times::AtLeast(2).is_met(1));
+        assert!(Times::AtMost(2).is_met(0));
+        assert!(!Times::AtMost(2).is_met(3));
+        assert!(Times::Never.is_met(0));
+        assert!(!Times::Never.is_met(1));
+    }
+
+    #[test]
+    fn deserialize_toml() {
+        for (toml, expected) in vec![
+            ("expect = { exactly = 1 }", Times::Exactly(1)),
+            ("expect = { at_least = 2 }", Times::AtLeast(2)),
+            ("expect = { at_most = 3 }", Times::AtMost(3)),
+            (r#"expect = "never""#, Times::Never),
+        ] {
+            match toml::from_str::<Expect>(toml) {
+                Ok(expect) => assert_eq!(expect.expect, expected),
+                Err(e) => assert!(false, e.to_string()),
+            }
+        }
+    }
+}
@@ -0,0 +1,508 @@
+// Copyright (c) 2018 libdeadmock developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! TLS configuration for upstream clients and the mock listener
+use crate::error::Error::{self, InvalidTlsConfig};
+use clap::ArgMatches;
+use getset::{Getters, Setters};
+use native_tls::{Certificate, Identity, TlsConnector};
+use rcgen::{CertificateParams, SanType};
+use rustls::internal::pemfile;
+use rustls::{NoClientAuth, PrivateKey, ServerConfig};
+use serde_derive::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+
+const PEM_CERT_BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_CERT_END: &str = "-----END CERTIFICATE-----";
+
+/// Client TLS configuration for proxied (upstream) requests.
+///
+/// # Example
+///
+/// ```
+/// # use libdeadmock::config;
+/// # use std::path::PathBuf;
+/// #
+/// # fn main() {
+///     // Trust an internal CA for one upstream only.
+///     let mut client_tls = config::ClientTls::default();
+///     client_tls.set_base_url(Some("https://staging.internal.com".to_string()));
+///     client_tls.set_ca_bundle(Some(PathBuf::from("certs/internal-ca.pem")));
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, Getters, Hash, Eq, PartialEq, Serialize, Setters)]
+pub struct ClientTls {
+    /// The upstream base url these settings apply to.  If this is not set, the settings apply to
+    /// every upstream without a more specific entry.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    #[set = "pub"]
+    base_url: Option<String>,
+    /// A PEM bundle of extra root certificates to trust.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    #[set = "pub"]
+    ca_bundle: Option<PathBuf>,
+    /// A PKCS#12 client identity (certificate chain and key) for mutual TLS.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    #[set = "pub"]
+    pkcs12: Option<PathBuf>,
+    /// The password for the PKCS#12 client identity.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    #[set = "pub"]
+    pkcs12_password: Option<String>,
+    /// A PEM client certificate chain for mutual TLS.  Requires `client_key`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    #[set = "pub"]
+    client_cert: Option<PathBuf>,
+    /// The PEM private key for `client_cert`.  This must be a PKCS#8 key (`BEGIN PRIVATE KEY`),
+    /// i.e. convert an RSA key with `openssl pkcs8 -topk8 -nocrypt`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    #[set = "pub"]
+    client_key: Option<PathBuf>,
+    /// Accept invalid upstream certificates and host names.  Only use this for local stand-ins.
+    #[serde(default)]
+    #[get = "pub"]
+    #[set = "pub"]
+    accept_invalid_certs: bool,
+}
+
+impl ClientTls {
+    /// Build a TLS connector from these settings.
+    crate fn connector(&self) -> Result<TlsConnector, Error> {
+        let mut builder = TlsConnector::builder();
+
+        if let Some(ca_bundle) = &self.ca_bundle {
+            let pem = fs::read_to_string(ca_bundle)?;
+            for cert in split_pem_certificates(&pem) {
+                let _ = builder.add_root_certificate(Certificate::from_pem(cert.as_bytes())?);
+            }
+        }
+
+        match (&self.pkcs12, &self.client_cert, &self.client_key) {
+            (Some(pkcs12), None, None) => {
+                let der = fs::read(pkcs12)?;
+                let password = self.pkcs12_password.as_ref().map_or("", String::as_str);
+                let _ = builder.identity(Identity::from_pkcs12(&der, password)?);
+            }
+            (None, Some(client_cert), Some(client_key)) => {
+                let cert = fs::read(client_cert)?;
+                let key = fs::read(client_key)?;
+                let _ = builder.identity(Identity::from_pkcs8(&cert, &key)?);
+            }
+            (None, None, None) => {}
+            _ => return Err(InvalidTlsConfig),
+        }
+
+        if self.accept_invalid_certs {
+            let _ = builder
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true);
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
+/// Split a PEM bundle into its individual certificates.
+fn split_pem_certificates(pem: &str) -> Vec<String> {
+    let mut certs = vec![];
+    let mut rest = pem;
+
+    while let Some(start) = rest.find(PEM_CERT_BEGIN) {
+        if let Some(len) = rest[start..].find(PEM_CERT_END) {
+            let end = start + len + PEM_CERT_END.len();
+            certs.push(rest[start..end].to_string());
+            rest = &rest[end..];
+        } else {
+            break;
+        }
+    }
+
+    certs
+}
+
+impl ClientTls {
+    /// Override the settings whose command line arguments are given, keeping the rest.
+    crate fn with_matches<'a>(mut self, matches: &'a ArgMatches<'a>) -> Result<Self, Error> {
+        if let Some(ca_bundle) = matches.value_of("upstream-ca") {
+            self.ca_bundle = Some(PathBuf::from(ca_bundle));
+        }
+        if let Some(pkcs12) = matches.value_of("upstream-pkcs12") {
+            self.pkcs12 = Some(PathBuf::from(pkcs12));
+        }
+        if let Some(pkcs12_password) = matches.value_of("upstream-pkcs12-password") {
+            self.pkcs12_password = Some(pkcs12_password.to_string());
+        }
+        if let Some(client_cert) = matches.value_of("upstream-cert") {
+            self.client_cert = Some(PathBuf::from(client_cert));
+        }
+        if let Some(client_key) = matches.value_of("upstream-key") {
+            self.client_key = Some(PathBuf::from(client_key));
+        }
+        if matches.is_present("upstream-insecure") {
+            self.accept_invalid_certs = true;
+        }
+
+        let has_pkcs12 = self.pkcs12.is_some();
+        let has_cert = self.client_cert.is_some();
+        let has_key = self.client_key.is_some();
+
+        if (has_pkcs12 && (has_cert || has_key)) || has_cert != has_key {
+            Err(InvalidTlsConfig)
+        } else {
+            Ok(self)
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a ArgMatches<'a>> for ClientTls {
+    type Error = Error;
+
+    fn try_from(matches: &'a ArgMatches<'a>) -> Result<Self, Error> {
+        Self::default().with_matches(matches)
+    }
+}
+
+/// The TLS listener configuration for deadmock.
+///
+/// # Example
+///
+/// ```
+/// # use libdeadmock::config;
+/// # use std::path::PathBuf;
+/// #
+/// # fn main() {
+///     // Serve with the given certificate chain and private key.
+///     let mut server_tls = config::ServerTls::default();
+///     server_tls.set_enabled(true);
+///     server_tls.set_cert_path(Some(PathBuf::from("certs/server.pem")));
+///     server_tls.set_key_path(Some(PathBuf::from("certs/server.key")));
+///
+///     // Serve with a self-signed certificate generated at startup.
+///     let mut self_signed = config::ServerTls::default();
+///     self_signed.set_enabled(true);
+///     self_signed.set_self_signed(true);
+/// # }
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, Getters, Hash, Eq, PartialEq, Serialize, Setters)]
+pub struct ServerTls {
+    /// Turn the TLS listener on.  If this is true, either `self_signed` or both `cert_path` and
+    /// `key_path` are required.
+    #[serde(default)]
+    #[get = "pub"]
+    #[set = "pub"]
+    enabled: bool,
+    /// The port for the TLS listener.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    #[set = "pub"]
+    port: Option<u16>,
+    /// A PEM certificate chain for the listener.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    #[set = "pub"]
+    cert_path: Option<PathBuf>,
+    /// The PEM (PKCS#8 or RSA) private key for `cert_path`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[get = "pub"]
+    #[set = "pub"]
+    key_path: Option<PathBuf>,
+    /// Generate a self-signed certificate for `localhost` at startup.  For local use only.
+    #[serde(default)]
+    #[get = "pub"]
+    #[set = "pub"]
+    self_signed: bool,
+}
+
+impl ServerTls {
+    /// Build the rustls server configuration from these settings.
+    ///
+    /// The listener offers `h2` and `http/1.1` with ALPN.
+    crate fn server_config(&self) -> Result<ServerConfig, Error> {
+        let (certs, key) = if self.self_signed {
+            let cert = rcgen::Certificate::from_params(self_signed_params())
+                .map_err(|_| InvalidTlsConfig)?;
+            let der = cert.serialize_der().map_err(|_| InvalidTlsConfig)?;
+            (
+                vec![rustls::Certificate(der)],
+                PrivateKey(cert.serialize_private_key_der()),
+            )
+        } else {
+            match (&self.cert_path, &self.key_path) {
+                (Some(cert_path), Some(key_path)) => (load_certs(cert_path)?, load_key(key_path)?),
+                _ => return Err(InvalidTlsConfig),
+            }
+        };
+
+        let mut server_config = ServerConfig::new(NoClientAuth::new());
+        server_config.set_single_cert(certs, key)?;
+        server_config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+        Ok(server_config)
+    }
+}
+
+/// The self-signed certificate is for `localhost`, plus the loopback addresses as IP address
+/// names, so clients connecting by IP accept it too.
+fn self_signed_params() -> CertificateParams {
+    let mut params = CertificateParams::new(vec!["localhost".to_string()]);
+    params
+        .subject_alt_names
+        .push(SanType::IpAddress(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+    params
+        .subject_alt_names
+        .push(SanType::IpAddress(IpAddr::V6(Ipv6Addr::LOCALHOST)));
+    params
+}
+
+/// Load a PEM certificate chain.
+fn load_certs(path: &PathBuf) -> Result<Vec<rustls::Certificate>, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    match pemfile::certs(&mut reader) {
+        Ok(ref certs) if certs.is_empty() => Err(InvalidTlsConfig),
+        Ok(certs) => Ok(certs),
+        Err(_) => Err(InvalidTlsConfig),
+    }
+}
+
+/// Load a PEM private key, trying PKCS#8 first and then RSA.
+fn load_key(path: &PathBuf) -> Result<PrivateKey, Error> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut keys = pemfile::pkcs8_private_keys(&mut reader).map_err(|_| InvalidTlsConfig)?;
+
+    if keys.is_empty() {
+        let mut reader = BufReader::new(File::open(path)?);
+        keys = pemfile::rsa_private_keys(&mut reader).map_err(|_| InvalidTlsConfig)?;
+    }
+
+    keys.into_iter().next().ok_or(InvalidTlsConfig)
+}
+
+impl ServerTls {
+    /// Override the settings whose command line arguments are given, keeping the rest.
+    ///
+    /// The listener is turned on by `tls`, `tls-self-signed`, or `tls-cert`, but never turned
+    /// off here.
+    crate fn with_matches<'a>(mut self, matches: &'a ArgMatches<'a>) -> Result<Self, Error> {
+        if let Some(port) = matches.value_of("tls-port") {
+            self.port = Some(port.parse::<u16>().map_err(|_| InvalidTlsConfig)?);
+        }
+        if let Some(cert_path) = matches.value_of("tls-cert") {
+            self.cert_path = Some(PathBuf::from(cert_path));
+        }
+        if let Some(key_path) = matches.value_of("tls-key") {
+            self.key_path = Some(PathBuf::from(key_path));
+        }
+        if matches.is_present("tls-self-signed") {
+            self.self_signed = true;
+        }
+        if matches.is_present("tls")
+            || matches.is_present("tls-self-signed")
+            || matches.is_present("tls-cert")
+        {
+            self.enabled = true;
+        }
+
+        if self.enabled
+            && !self.self_signed
+            && (self.cert_path.is_none() || self.key_path.is_none())
+        {
+            Err(InvalidTlsConfig)
+        } else {
+            Ok(self)
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a ArgMatches<'a>> for ServerTls {
+    type Error = Error;
+
+    fn try_from(matches: &'a ArgMatches<'a>) -> Result<Self, Error> {
+        Self::default().with_matches(matches)
+    }
+}
+
+#[cfg(test)]
+crate mod test {
+    use super::{self_signed_params, split_pem_certificates, ClientTls, ServerTls};
+    use clap::{App, Arg};
+    use rcgen::SanType;
+    use std::convert::TryFrom;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::path::PathBuf;
+
+    const CLIENT_TLS_TOML: &str = r#"base_url = "https://staging.internal.com"
+ca_bundle = "certs/internal-ca.pem"
+client_cert = "certs/client.pem"
+client_key = "certs/client.key"
+accept_invalid_certs = false
+"#;
+    const BUNDLE: &str = "junk
+-----BEGIN CERTIFICATE-----
+MIIB
+-----END CERTIFICATE-----
+-----BEGIN CERTIFICATE-----
+MIIC
+-----END CERTIFICATE-----
+";
+
+    fn test_cli() -> App<'static, 'static> {
+        App::new("tls-config-test")
+            .version("1")
+            .author("Yoda")
+            .about("command line for tls config testing")
+            .arg(
+                Arg::with_name("upstream-ca")
+                    .long("upstream-ca")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("upstream-pkcs12")
+                    .long("upstream-pkcs12")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("upstream-cert")
+                    .long("upstream-cert")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("upstream-key")
+                    .long("upstream-key")
+                    .takes_value(true),
+            )
+            .arg(Arg::with_name("upstream-insecure").long("upstream-insecure"))
+            .arg(Arg::with_name("tls").long("tls"))
+            .arg(
+                Arg::with_name("tls-port")
+                    .long("tls-port")
+                    .takes_value(true),
+            )
+            .arg(
+                Arg::with_name("tls-cert")
+                    .long("tls-cert")
+                    .takes_value(true),
+            )
+            .arg(Arg::with_name("tls-key").long("tls-key").takes_value(true))
+            .arg(Arg::with_name("tls-self-signed").long("tls-self-signed"))
+    }
+
+    crate fn staging_client_tls() -> ClientTls {
+        let mut client_tls = ClientTls::default();
+        client_tls.base_url = Some("https://staging.internal.com".to_string());
+        client_tls.ca_bundle = Some(PathBuf::from("certs/internal-ca.pem"));
+        client_tls.client_cert = Some(PathBuf::from("certs/client.pem"));
+        client_tls.client_key = Some(PathBuf::from("certs/client.key"));
+        client_tls
+    }
+
+    #[test]
+    fn deserialize_client_tls_toml() {
+        match toml::from_str::<ClientTls>(CLIENT_TLS_TOML) {
+            Ok(deserialized) => assert_eq!(deserialized, staging_client_tls()),
+            Err(e) => assert!(false, e.to_string()),
+        }
+    }
+
+    #[test]
+    fn split_bundle() {
+        let certs = split_pem_certificates(BUNDLE);
+        assert_eq!(certs.len(), 2);
+        assert!(certs[0].contains("MIIB"));
+        assert!(certs[1].contains("MIIC"));
+    }
+
+    #[test]
+    fn default_connector() {
+        assert!(ClientTls::default().connector().is_ok());
+    }
+
+    #[test]
+    fn from_args() {
+        let arg_vec = vec!["test-cli", "--upstream-ca", "ca.pem", "--upstream-insecure"];
+        let matches = test_cli().get_matches_from(arg_vec);
+        match ClientTls::try_from(&matches) {
+            Ok(client_tls) => {
+                assert_eq!(client_tls.ca_bundle(), &Some(PathBuf::from("ca.pem")));
+                assert!(client_tls.accept_invalid_certs());
+            }
+            Err(_) => assert!(false, "Not expected to error!"),
+        }
+    }
+
+    #[test]
+    fn cert_requires_key() {
+        let arg_vec = vec!["test-cli", "--upstream-cert", "client.pem"];
+        let matches = test_cli().get_matches_from(arg_vec);
+        assert!(ClientTls::try_from(&matches).is_err());
+
+        let arg_vec = vec![
+            "test-cli",
+            "--upstream-pkcs12",
+            "client.p12",
+            "--upstream-key",
+            "client.key",
+        ];
+        let matches = test_cli().get_matches_from(arg_vec);
+        assert!(ClientTls::try_from(&matches).is_err());
+    }
+
+    #[test]
+    fn server_tls_from_args() {
+        let arg_vec = vec!["test-cli", "--tls-self-signed", "--tls-port", "32443"];
+        let matches = test_cli().get_matches_from(arg_vec);
+        match ServerTls::try_from(&matches) {
+            Ok(server_tls) => {
+                assert!(server_tls.enabled());
+                assert!(server_tls.self_signed());
+                assert_eq!(server_tls.port(), &Some(32443));
+            }
+            Err(_) => assert!(false, "Not expected to error!"),
+        }
+
+        let matches = test_cli().get_matches_from(vec!["test-cli"]);
+        match ServerTls::try_from(&matches) {
+            Ok(server_tls) => assert_eq!(server_tls, ServerTls::default()),
+            Err(_) => assert!(false, "Not expected to error!"),
+        }
+    }
+
+    #[test]
+    fn server_tls_requires_cert_and_key() {
+        let arg_vec = vec!["test-cli", "--tls"];
+        let matches = test_cli().get_matches_from(arg_vec);
+        assert!(ServerTls::try_from(&matches).is_err());
+
+        let arg_vec = vec!["test-cli", "--tls-cert", "server.pem"];
+        let matches = test_cli().get_matches_from(arg_vec);
+        assert!(ServerTls::try_from(&matches).is_err());
+    }
+
+    #[test]
+    fn self_signed_server_config() {
+        let mut server_tls = ServerTls::default();
+        let _ = server_tls.set_enabled(true);
+        assert!(server_tls.server_config().is_err());
+
+        let _ = server_tls.set_self_signed(true);
+        assert!(server_tls.server_config().is_ok());
+
+        let names = self_signed_params().subject_alt_names;
+        assert!(names.contains(&SanType::DnsName("localhost".to_string())));
+        assert!(names.contains(&SanType::IpAddress(IpAddr::V4(Ipv4Addr::LOCALHOST))));
+        assert!(!names.contains(&SanType::DnsName("127.0.0.1".to_string())));
+    }
+}
@@ -0,0 +1,276 @@
+// Copyright (c) 2018 libdeadmock developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! `libdeadmock` upstream request configuration
+use crate::config::Response;
+use crate::error::Error::{self, InvalidUpstreamConfig};
+use clap::ArgMatches;
+use getset::{Getters, Setters};
+use http::Method;
+use serde_derive::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::time::Duration;
+
+/// The default total timeout for an upstream request attempt, in milliseconds.
+const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+/// The default delay before the first retry, in milliseconds.
+const DEFAULT_RETRY_BACKOFF_MS: u64 = 100;
+
+/// The timeout and retry configuration for proxied (upstream) requests.
+///
+/// These are the global settings.  Each can be overridden per mapping on `config::Response`.
+///
+/// # Example
+///
+/// ```
+/// # use libdeadmock::config;
+/// # use std::time::Duration;
+/// #
+/// # fn main() {
+///     let mut upstream_config = config::Upstream::default();
+///     assert_eq!(upstream_config.timeout(), Duration::from_secs(10));
+///
+///     // Allow two retries, with 250ms, then 500ms, between attempts.
+///     upstream_config.set_retries(2);
+///     upstream_config.set_retry_backoff_ms(250);
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Deserialize, Getters, Hash, Eq, PartialEq, Serialize, Setters)]
+#[serde(default)]
+pub struct Upstream {
+    /// The timeout for establishing the upstream connection, in milliseconds.
+    #[get = "pub"]
+    #[set = "pub"]
+    connect_timeout_ms: Option<u64>,
+    /// The total timeout for one upstream request attempt, in milliseconds.
+    #[get = "pub"]
+    #[set = "pub"]
+    timeout_ms: u64,
+    /// The number of times a failed upstream request is retried.
+    #[get = "pub"]
+    #[set = "pub"]
+    retries: u32,
+    /// The delay before the first retry, in milliseconds.  The delay doubles on each retry.
+    #[get = "pub"]
+    #[set = "pub"]
+    retry_backoff_ms: u64,
+    /// Retry failed requests with a non-idempotent method, i.e. `POST` or `PATCH`, as well.
+    /// These may have reached the upstream before failing, so are not retried by default.
+    #[get = "pub"]
+    #[set = "pub"]
+    retry_non_idempotent: bool,
+}
+
+impl Default for Upstream {
+    fn default() -> Self {
+        Self {
+            connect_timeout_ms: None,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            retries: 0,
+            retry_backoff_ms: DEFAULT_RETRY_BACKOFF_MS,
+            retry_non_idempotent: false,
+        }
+    }
+}
+
+impl Upstream {
+    /// The upstream configuration for the given response, i.e. these settings with any
+    /// overrides from the response configuration applied.
+    pub fn for_response(&self, response: &Response) -> Self {
+        Self {
+            connect_timeout_ms: response.connect_timeout_ms().or(self.connect_timeout_ms),
+            timeout_ms: response.timeout_ms().unwrap_or(self.timeout_ms),
+            retries: response.retries().unwrap_or(self.retries),
+            retry_backoff_ms: response.retry_backoff_ms().unwrap_or(self.retry_backoff_ms),
+            retry_non_idempotent: response
+                .retry_non_idempotent()
+                .unwrap_or(self.retry_non_idempotent),
+        }
+    }
+
+    /// The number of times a failed request with the given method is retried.
+    ///
+    /// Only idempotent methods (`GET`, `HEAD`, `OPTIONS`, `PUT`, and `DELETE`) are retried,
+    /// unless `retry_non_idempotent` is set.
+    pub fn retries_for(&self, method: &Method) -> u32 {
+        let idempotent = match *method {
+            Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE => true,
+            _ => false,
+        };
+
+        if idempotent || self.retry_non_idempotent {
+            self.retries
+        } else {
+            0
+        }
+    }
+
+    /// Override the settings whose command line arguments are given, keeping the rest.
+    crate fn with_matches<'a>(mut self, matches: &'a ArgMatches<'a>) -> Result<Self, Error> {
+        if let Some(connect_timeout_ms) = parse(matches, "connect-timeout")? {
+            self.connect_timeout_ms = Some(connect_timeout_ms);
+        }
+        if let Some(timeout_ms) = parse(matches, "timeout")? {
+            self.timeout_ms = timeout_ms;
+        }
+        if let Some(retries) = parse(matches, "retries")? {
+            self.retries = retries;
+        }
+        if let Some(retry_backoff_ms) = parse(matches, "retry-backoff")? {
+            self.retry_backoff_ms = retry_backoff_ms;
+        }
+        if matches.is_present("retry-non-idempotent") {
+            self.retry_non_idempotent = true;
+        }
+        Ok(self)
+    }
+
+    /// The connect timeout as a `Duration`.
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout_ms.map(Duration::from_millis)
+    }
+
+    /// The total timeout for one attempt as a `Duration`.
+    pub fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+
+    /// The delay before the given retry (zero-based).
+    pub fn backoff(&self, retry: u32) -> Duration {
+        Duration::from_millis(self.retry_backoff_ms.saturating_mul(1_u64 << retry.min(16)))
+    }
+}
+
+fn parse<T: std::str::FromStr>(matches: &ArgMatches<'_>, name: &str) -> Result<Option<T>, Error> {
+    if let Some(value) = matches.value_of(name) {
+        Ok(Some(value.parse().map_err(|_| InvalidUpstreamConfig)?))
+    } else {
+        Ok(None)
+    }
+}
+
+impl<'a> TryFrom<&'a ArgMatches<'a>> for Upstream {
+    type Error = Error;
+
+    fn try_from(matches: &'a ArgMatches<'a>) -> Result<Self, Error> {
+        Self::default().with_matches(matches)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Upstream;
+    use crate::config::Response;
+    use clap::{App, Arg};
+    use http::Method;
+    use std::convert::TryFrom;
+    use std::time::Duration;
+
+    fn test_cli() -> App<'static, 'static> {
+        App::new("upstream-config-test")
+            .version("1")
+            .author("Yoda")
+            .about("command line for upstream config testing")
+            .arg(
+                Arg::with_name("connect-timeout")
+                    .long("connect-timeout")
+                    .takes_value(true)
+                    .value_name("MILLIS"),
+            )
+            .arg(
+                Arg::with_name("timeout")
+                    .long("timeout")
+                    .takes_value(true)
+                    .value_name("MILLIS"),
+            )
+            .arg(
+                Arg::with_name("retries")
+                    .long("retries")
+                    .takes_value(true)
+                    .value_name("COUNT"),
+            )
+            .arg(
+                Arg::with_name("retry-backoff")
+                    .long("retry-backoff")
+                    .takes_value(true)
+                    .value_name("MILLIS"),
+            )
+            .arg(Arg::with_name("retry-non-idempotent").long("retry-non-idempotent"))
+    }
+
+    #[test]
+    fn defaults() {
+        let upstream_config = Upstream::default();
+        assert!(upstream_config.connect_timeout().is_none());
+        assert_eq!(upstream_config.timeout(), Duration::from_secs(10));
+        assert_eq!(*upstream_config.retries(), 0);
+    }
+
+    #[test]
+    fn from_all_args() {
+        let arg_vec = vec![
+            "test-cli",
+            "--connect-timeout",
+            "500",
+            "--timeout",
+            "2000",
+            "--retries",
+            "3",
+            "--retry-backoff",
+            "50",
+        ];
+        let matches = test_cli().get_matches_from(arg_vec);
+        match Upstream::try_from(&matches) {
+            Ok(upstream_config) => {
+                assert_eq!(
+                    upstream_config.connect_timeout(),
+                    Some(Duration::from_millis(500))
+                );
+                assert_eq!(upstream_config.timeout(), Duration::from_secs(2));
+                assert_eq!(*upstream_config.retries(), 3);
+                assert_eq!(upstream_config.backoff(0), Duration::from_millis(50));
+                assert_eq!(upstream_config.backoff(2), Duration::from_millis(200));
+            }
+            Err(_) => assert!(false, "Not expected to error!"),
+        }
+    }
+
+    #[test]
+    fn invalid_args() {
+        let arg_vec = vec!["test-cli", "--timeout", "abc"];
+        let matches = test_cli().get_matches_from(arg_vec);
+        match Upstream::try_from(&matches) {
+            Ok(_) => assert!(false, "Not expected to succeed!"),
+            Err(e) => assert_eq!(format!("{}", e), "invalid upstream configuration!"),
+        }
+    }
+
+    #[test]
+    fn response_overrides() {
+        let response: Response = serde_json::from_str(r#"{"timeout_ms":250,"retries":1}"#)
+            .expect("Unable to deserialize response!");
+        let upstream_config = Upstream::default().for_response(&response);
+        assert!(upstream_config.connect_timeout().is_none());
+        assert_eq!(upstream_config.timeout(), Duration::from_millis(250));
+        assert_eq!(*upstream_config.retries(), 1);
+        assert_eq!(upstream_config.backoff(0), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn idempotent_retries() {
+        let mut upstream_config = Upstream::default();
+        let _ = upstream_config.set_retries(2);
+        assert_eq!(upstream_config.retries_for(&Method::GET), 2);
+        assert_eq!(upstream_config.retries_for(&Method::DELETE), 2);
+        assert_eq!(upstream_config.retries_for(&Method::POST), 0);
+        assert_eq!(upstream_config.retries_for(&Method::PATCH), 0);
+
+        let _ = upstream_config.set_retry_non_idempotent(true);
+        assert_eq!(upstream_config.retries_for(&Method::POST), 2);
+    }
+}
@@ -0,0 +1,135 @@
+// Copyright (c) 2018 libdeadmock developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! `libdeadmock` url matching options
+use getset::{Getters, Setters};
+use percent_encoding::percent_decode;
+use serde_derive::{Deserialize, Serialize};
+
+/// How the request path is compared with the `url` and `url_pattern` of a mapping.
+///
+/// # Example
+///
+/// In TOML, `url_options = { case_insensitive = true, ignore_trailing_slash = true }`.
+///
+/// ```
+/// # use libdeadmock::config::UrlOptions;
+/// #
+/// # fn main() {
+///     let mut options = UrlOptions::default();
+///     let _ = options.set_normalize(true).set_ignore_trailing_slash(true);
+///     assert_eq!(options.apply("/users//%31/"), "/users/1");
+/// # }
+/// ```
+#[derive(
+    Clone, Copy, Debug, Default, Deserialize, Eq, Getters, Hash, PartialEq, Serialize, Setters,
+)]
+pub struct UrlOptions {
+    /// Compare the paths ignoring case.  The `url_pattern` is matched ignoring case too.
+    #[serde(default)]
+    #[get = "pub"]
+    #[set = "pub"]
+    case_insensitive: bool,
+    /// Ignore a trailing slash, so `/users/1/` is `/users/1`.
+    #[serde(default)]
+    #[get = "pub"]
+    #[set = "pub"]
+    ignore_trailing_slash: bool,
+    /// Percent-decode the paths and collapse repeated slashes, so `/users//%31` is `/users/1`.
+    #[serde(default)]
+    #[get = "pub"]
+    #[set = "pub"]
+    normalize: bool,
+}
+
+impl UrlOptions {
+    /// Apply the options to the given path.
+    ///
+    /// The path is normalized first, then the trailing slash is removed, then it is lowercased.
+    /// The root path `/` keeps its slash.
+    pub fn apply(&self, path: &str) -> String {
+        let mut path = if self.normalize {
+            collapse_slashes(&percent_decode(path.as_bytes()).decode_utf8_lossy())
+        } else {
+            path.to_string()
+        };
+
+        if self.ignore_trailing_slash {
+            while path.len() > 1 && path.ends_with('/') {
+                let _ = path.pop();
+            }
+        }
+
+        if self.case_insensitive {
+            path.to_lowercase()
+        } else {
+            path
+        }
+    }
+}
+
+fn collapse_slashes(path: &str) -> String {
+    let mut collapsed = String::with_capacity(path.len());
+    for c in path.chars() {
+        if !(c == '/' && collapsed.ends_with('/')) {
+            collapsed.push(c);
+        }
+    }
+    collapsed
+}
+
+#[cfg(test)]
+mod test {
+    use super::UrlOptions;
+
+    #[test]
+    fn default_is_exact() {
+        let options = UrlOptions::default();
+        assert_eq!(options.apply("/Users//%31/"), "/Users//%31/");
+    }
+
+    #[test]
+    fn each_option() {
+        let mut case_insensitive = UrlOptions::default();
+        let _ = case_insensitive.set_case_insensitive(true);
+        assert_eq!(case_insensitive.apply("/Users/1"), "/users/1");
+
+        let mut trailing_slash = UrlOptions::default();
+        let _ = trailing_slash.set_ignore_trailing_slash(true);
+        assert_eq!(trailing_slash.apply("/users/1//"), "/users/1");
+        assert_eq!(trailing_slash.apply("/"), "/");
+
+        let mut normalize = UrlOptions::default();
+        let _ = normalize.set_normalize(true);
+        assert_eq!(normalize.apply("//users//1"), "/users/1");
+        assert_eq!(normalize.apply("/caf%C3%A9/a%20b"), "/café/a b");
+    }
+
+    #[test]
+    fn all_options() {
+        let mut options = UrlOptions::default();
+        let _ = options
+            .set_case_insensitive(true)
+            .set_ignore_trailing_slash(true)
+            .set_normalize(true);
+        assert_eq!(options.apply("/Users//%31/"), "/users/1");
+        assert_eq!(options.apply("/users/1"), "/users/1");
+    }
+
+    #[test]
+    fn deserialize() {
+        match toml::from_str::<UrlOptions>("case_insensitive = true") {
+            Ok(options) => {
+                assert!(options.case_insensitive());
+                assert!(!options.ignore_trailing_slash());
+                assert!(!options.normalize());
+            }
+            Err(e) => assert!(false, e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,324 @@
+// Copyright (c) 2018 libdeadmock developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! `libdeadmock` mapping validation
+use crate::config::{Header, HeaderPattern, Mapping, Mappings, Pattern};
+use crate::error::Error::{self, InvalidMappings};
+use crate::util;
+use getset::Getters;
+use http::header::{HeaderName, HeaderValue};
+use http::StatusCode;
+use libeither::Either;
+use slog::{warn, Logger};
+use slog_try::try_warn;
+use std::collections::HashSet;
+use std::fmt;
+use std::path::Path;
+use uuid::Uuid;
+
+/// How serious a validation problem is.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Severity {
+    /// The mapping is broken, and fails startup or a reload.
+    Error,
+    /// The mapping loads, but likely doesn't do what was intended.
+    Warning,
+}
+
+/// A problem found while validating a mapping.
+#[derive(Clone, Debug, Eq, Getters, Hash, PartialEq)]
+pub struct Problem {
+    /// How serious the problem is.
+    #[get = "pub"]
+    severity: Severity,
+    /// The id of the mapping with the problem.
+    #[get = "pub"]
+    id: Option<Uuid>,
+    /// The name of the mapping with the problem.
+    #[get = "pub"]
+    name: String,
+    /// What is wrong with the mapping.
+    #[get = "pub"]
+    message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.id {
+            Some(id) => write!(f, "'{}' ({}): {}", self.name, id, self.message),
+            None => write!(f, "'{}': {}", self.name, self.message),
+        }
+    }
+}
+
+/// Validate every mapping, returning all of the problems found.
+///
+/// This checks that the `body_file_name` files exist under `files_path`, that status codes are
+/// valid, and that header names and values are legal.  A mapping without any request criteria
+/// is a warning, as it can never match.  Patterns that don't compile are reported too.
+pub fn validate(mappings: &Mappings, files_path: &Path) -> Vec<Problem> {
+    let mut file_names = HashSet::new();
+    let _ = util::visit_dirs(files_path, &mut |entry| -> Result<(), Error> {
+        let _ = file_names.insert(entry.file_name().to_string_lossy().to_string());
+        Ok(())
+    });
+
+    let mut sorted: Vec<&Mapping> = mappings.inner().values().map(AsRef::as_ref).collect();
+    sorted.sort_by(|left, right| left.name().cmp(right.name()));
+
+    sorted
+        .into_iter()
+        .flat_map(|mapping| {
+            validate_mapping(mapping, &file_names)
+                .into_iter()
+                .map(move |(severity, message)| Problem {
+                    severity,
+                    id: *mapping.id(),
+                    name: mapping.name().clone(),
+                    message,
+                })
+        })
+        .collect()
+}
+
+impl Mappings {
+    /// Validate every mapping, failing with all of the errors found.  Warnings are logged to
+    /// `stderr`.
+    ///
+    /// See [`validate`](crate::config::validate) for the checks that are done.
+    pub fn validate(&self, files_path: &Path, stderr: &Option<Logger>) -> Result<(), Error> {
+        let (warnings, errors): (Vec<Problem>, Vec<Problem>) = validate(self, files_path)
+            .into_iter()
+            .partition(|problem| problem.severity == Severity::Warning);
+
+        for warning in warnings {
+            try_warn!(stderr, "{}", warning);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidMappings(errors))
+        }
+    }
+}
+
+fn validate_mapping(mapping: &Mapping, file_names: &HashSet<String>) -> Vec<(Severity, String)> {
+    let mut warnings = vec![];
+    let mut messages = vec![];
+    let request = mapping.request();
+    let response = mapping.response();
+
+    if request.method().is_none()
+        && request.method_pattern().is_none()
+        && request.url().is_none()
+        && request.url_pattern().is_none()
+        && request.headers().is_empty()
+        && request.headers_pattern().is_empty()
+        && request.header().is_none()
+        && request.header_pattern().is_none()
+    {
+        warnings.push("no request criteria, the mapping can never match".to_string());
+    }
+
+    for header in request.headers().iter().chain(request.header()) {
+        check_header("request header", header, &mut messages);
+    }
+    for header_pattern in request
+        .headers_pattern()
+        .iter()
+        .chain(request.header_pattern())
+    {
+        check_header_pattern(header_pattern, &mut messages);
+    }
+    for (field, pattern) in request.patterns() {
+        if let Some(e) = pattern.error() {
+            messages.push(format!("invalid {} '{}': {}", field, pattern, e));
+        }
+    }
+
+    if let Some(status) = response.status() {
+        if StatusCode::from_u16(*status).is_err() {
+            messages.push(format!("invalid status code {}", status));
+        }
+    }
+    for header in response.headers() {
+        check_header("response header", header, &mut messages);
+    }
+    for header in response.additional_proxy_request_headers() {
+        check_header("additional proxy request header", header, &mut messages);
+    }
+    if let Some(body_file_name) = response.body_file_name() {
+        if !file_names.contains(body_file_name) {
+            messages.push(format!("body file '{}' not found", body_file_name));
+        }
+    }
+
+    warnings
+        .into_iter()
+        .map(|message| (Severity::Warning, message))
+        .chain(
+            messages
+                .into_iter()
+                .map(|message| (Severity::Error, message)),
+        )
+        .collect()
+}
+
+fn check_header(kind: &str, header: &Header, messages: &mut Vec<String>) {
+    if HeaderName::from_bytes(header.key().as_bytes()).is_err() {
+        messages.push(format!("invalid {} name '{}'", kind, header.key()));
+    }
+    if HeaderValue::from_str(header.value()).is_err() {
+        messages.push(format!("invalid {} value '{}'", kind, header.value()));
+    }
+}
+
+fn check_header_pattern(header_pattern: &HeaderPattern, messages: &mut Vec<String>) {
+    check_either(
+        "header pattern key",
+        header_pattern.key(),
+        messages,
+        |key| HeaderName::from_bytes(key.as_bytes()).is_ok(),
+    );
+    check_either(
+        "header pattern value",
+        header_pattern.value(),
+        messages,
+        |value| HeaderValue::from_str(value).is_ok(),
+    );
+}
+
+/// Check an exact (left) header part with `is_legal`.  A pattern (right) header part is
+/// checked with the other patterns.
+fn check_either<F>(
+    field: &str,
+    either: &Either<String, Pattern>,
+    messages: &mut Vec<String>,
+    is_legal: F,
+) where
+    F: Fn(&str) -> bool,
+{
+    if let Ok(exact) = either.left_ref() {
+        if !is_legal(exact) {
+            messages.push(format!("invalid {} '{}'", field, exact));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{validate, Severity};
+    use crate::config::Mappings;
+    use std::env;
+    use std::fs;
+    use uuid::Uuid;
+
+    const VALID_MAPPING: &str = r#"name = "valid"
+priority = 1
+
+[request]
+url_pattern = "^/valid/.*"
+
+[[request.headers_pattern]]
+[request.headers_pattern.key]
+left = "x-request-id"
+
+[request.headers_pattern.value]
+right = '^\d+$'
+
+[response]
+status = 200
+body_file_name = "valid.json"
+
+[[response.headers]]
+key = "Content-Type"
+value = "application/json"
+"#;
+    const INVALID_MAPPING: &str = r#"name = "invalid"
+priority = 1
+
+[request.header_pattern.key]
+left = "x bad name"
+
+[request.header_pattern.value]
+right = "*"
+
+[response]
+status = 1000
+body_file_name = "missing.json"
+
+[[response.headers]]
+key = "Content-Type"
+value = "bad\nvalue"
+"#;
+    const EMPTY_CRITERIA_MAPPING: &str = r#"name = "empty"
+priority = 1
+
+[request]
+
+[response]
+status = 204
+"#;
+
+    #[test]
+    fn all_problems_together() {
+        let root = env::temp_dir().join(format!("deadmock-validate-{}", Uuid::new_v4()));
+        let mappings_path = root.join("mappings");
+        let files_path = root.join("files");
+        assert!(fs::create_dir_all(&mappings_path).is_ok());
+        assert!(fs::create_dir_all(&files_path).is_ok());
+        assert!(fs::write(files_path.join("valid.json"), "{}").is_ok());
+        assert!(fs::write(mappings_path.join("valid.toml"), VALID_MAPPING).is_ok());
+
+        match Mappings::load(&mappings_path, &None) {
+            Ok(mappings) => {
+                assert!(validate(&mappings, &files_path).is_empty());
+                assert!(mappings.validate(&files_path, &None).is_ok());
+            }
+            Err(e) => assert!(false, e.to_string()),
+        }
+
+        assert!(fs::write(mappings_path.join("invalid.toml"), INVALID_MAPPING).is_ok());
+        assert!(fs::write(mappings_path.join("empty.toml"), EMPTY_CRITERIA_MAPPING).is_ok());
+
+        match Mappings::load(&mappings_path, &None) {
+            Ok(mappings) => {
+                let problems = validate(&mappings, &files_path);
+                let messages: Vec<&str> = problems.iter().map(|p| p.message().as_str()).collect();
+                assert_eq!(problems.len(), 6, "{:?}", messages);
+                assert_eq!(problems[0].name(), "empty");
+                assert!(messages[0].contains("can never match"));
+                assert_eq!(problems[0].severity(), &Severity::Warning);
+                assert!(problems[1..].iter().all(|p| p.name() == "invalid"));
+                assert!(messages[1].contains("header pattern key"));
+                assert!(messages[2].contains("header pattern value '*'"));
+                assert!(messages[3].contains("status code 1000"));
+                assert!(messages[4].contains("response header value"));
+                assert!(messages[5].contains("missing.json"));
+                assert!(problems[1..]
+                    .iter()
+                    .all(|p| p.severity() == &Severity::Error));
+                match mappings.validate(&files_path, &None) {
+                    Ok(_) => assert!(false, "Not expected to succeed!"),
+                    Err(e) => assert!(e.to_string().starts_with("5 invalid mapping problem(s)")),
+                }
+            }
+            Err(e) => assert!(false, e.to_string()),
+        }
+
+        // An empty criteria mapping on its own is only a warning.
+        assert!(fs::remove_file(mappings_path.join("invalid.toml")).is_ok());
+        match Mappings::load(&mappings_path, &None) {
+            Ok(mappings) => assert!(mappings.validate(&files_path, &None).is_ok()),
+            Err(e) => assert!(false, e.to_string()),
+        }
+
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -7,32 +7,124 @@
 // modified, or distributed except according to those terms.
 
 //! Errors generated by the library
+use http::StatusCode;
 use std::fmt;
 
 #[derive(Debug)]
 /// Errors generated by the library
 pub enum Error {
+    ///
+    Blocking(tokio_threadpool::BlockingError),
+    ///
+    DuplicateMappingId(uuid::Uuid),
     ///
     InvalidHeaderName(http::header::InvalidHeaderName),
     ///
     InvalidHeaderValue(http::header::InvalidHeaderValue),
     ///
+    InvalidMappingFile(std::path::PathBuf, String),
+    ///
+    InvalidMappings(Vec<crate::config::Problem>),
+    ///
+    InvalidPattern(regex::Error),
+    ///
     InvalidProxyConfig,
     ///
+    InvalidProxyUri(http::uri::InvalidUri),
+    ///
+    InvalidRuntimeConfig,
+    ///
+    InvalidTlsConfig,
+    ///
+    InvalidUpstreamConfig,
+    ///
     IO(std::io::Error),
     ///
-    MappingKeyCollision,
+    MappingKeyCollision(uuid::Uuid, std::path::PathBuf, std::path::PathBuf),
     ///
     MappingNotFound,
     ///
+    TlsInit(native_tls::Error),
+    ///
+    TlsServer(rustls::TLSError),
+    ///
     TomlDe(toml::de::Error),
+    ///
+    TomlEnv(tomlenv::Error),
+    ///
+    TomlSer(toml::ser::Error),
+    ///
+    UnmetExpectations(Vec<crate::server::Unmet>),
+    ///
+    UpstreamCanceled,
+    ///
+    UpstreamBody(std::string::FromUtf8Error),
+    ///
+    UpstreamClient(Box<Error>),
+    ///
+    UpstreamRequest(http::Error),
+    ///
+    Watch(notify::Error),
+}
+
+impl Error {
+    /// The HTTP status code to respond with when this error occurs while handling a request.
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            Error::MappingNotFound => StatusCode::NOT_FOUND,
+            Error::UpstreamBody(_) | Error::UpstreamCanceled | Error::UpstreamClient(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Error::Blocking(e) => write!(f, "unable to run blocking work: {}", e),
+            Error::DuplicateMappingId(id) => write!(f, "duplicate mapping id '{}'", id),
+            Error::InvalidMappingFile(path, e) => {
+                write!(f, "invalid mapping file '{}': {}", path.display(), e)
+            }
+            Error::InvalidMappings(problems) => {
+                write!(f, "{} invalid mapping problem(s)", problems.len())?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+            Error::InvalidPattern(e) => write!(f, "invalid pattern: {}", e),
             Error::InvalidProxyConfig => write!(f, "invalid proxy configuration!"),
+            Error::InvalidProxyUri(e) => write!(f, "unable to parse proxy uri: {}", e),
+            Error::InvalidRuntimeConfig => write!(f, "invalid runtime configuration!"),
+            Error::InvalidTlsConfig => write!(f, "invalid TLS configuration!"),
+            Error::InvalidUpstreamConfig => write!(f, "invalid upstream configuration!"),
             Error::IO(e) => write!(f, "{}", e),
+            Error::MappingKeyCollision(id, first, second) => write!(
+                f,
+                "duplicate mapping id '{}' in '{}' and '{}'",
+                id,
+                first.display(),
+                second.display()
+            ),
+            Error::TlsInit(e) => write!(f, "TLS initialization failed: {}", e),
+            Error::TlsServer(e) => write!(f, "unable to configure the TLS listener: {}", e),
+            Error::TomlEnv(e) => write!(f, "{}", e),
+            Error::TomlSer(e) => write!(f, "{}", e),
+            Error::UnmetExpectations(unmet) => {
+                write!(f, "{} unmet expectation(s)", unmet.len())?;
+                for expectation in unmet {
+                    write!(f, "\n  {}", expectation)?;
+                }
+                Ok(())
+            }
+            Error::UpstreamBody(e) => write!(f, "the upstream response body is not UTF-8: {}", e),
+            Error::UpstreamCanceled => write!(f, "the upstream request was canceled!"),
+            Error::UpstreamClient(e) => write!(f, "unable to create the upstream client: {}", e),
+            Error::UpstreamRequest(e) => write!(f, "unable to create upstream request: {}", e),
+            Error::Watch(e) => write!(f, "unable to watch for changes: {}", e),
             _ => write!(f, ""),
         }
     }
@@ -44,12 +136,54 @@ impl From<std::io::Error> for Error {
     }
 }
 
+impl From<tokio_threadpool::BlockingError> for Error {
+    fn from(e: tokio_threadpool::BlockingError) -> Self {
+        Error::Blocking(e)
+    }
+}
+
+impl From<regex::Error> for Error {
+    fn from(e: regex::Error) -> Self {
+        Error::InvalidPattern(e)
+    }
+}
+
 impl From<toml::de::Error> for Error {
     fn from(e: toml::de::Error) -> Self {
         Error::TomlDe(e)
     }
 }
 
+impl From<tomlenv::Error> for Error {
+    fn from(e: tomlenv::Error) -> Self {
+        Error::TomlEnv(e)
+    }
+}
+
+impl From<native_tls::Error> for Error {
+    fn from(e: native_tls::Error) -> Self {
+        Error::TlsInit(e)
+    }
+}
+
+impl From<notify::Error> for Error {
+    fn from(e: notify::Error) -> Self {
+        Error::Watch(e)
+    }
+}
+
+impl From<rustls::TLSError> for Error {
+    fn from(e: rustls::TLSError) -> Self {
+        Error::TlsServer(e)
+    }
+}
+
+impl From<toml::ser::Error> for Error {
+    fn from(e: toml::ser::Error) -> Self {
+        Error::TomlSer(e)
+    }
+}
+
 impl From<http::header::InvalidHeaderName> for Error {
     fn from(e: http::header::InvalidHeaderName) -> Self {
         Error::InvalidHeaderName(e)
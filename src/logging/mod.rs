@@ -8,13 +8,14 @@
 
 //! Logging for the server.
 use crate::config::Runtime;
-use crate::error::Error;
+use crate::error::Error::{self, InvalidRuntimeConfig};
 use clap::ArgMatches;
 use getset::Getters;
 use slog::{o, Drain, Level, Logger};
 use slog_async::Async;
 use slog_term::{CompactFormat, TermDecorator};
 use std::convert::TryFrom;
+use std::str::FromStr;
 
 /// `slog` loggers for stdout/stderr.
 #[derive(Clone, Debug, Default, Getters)]
@@ -32,19 +33,9 @@ impl Loggers {
     pub fn split(&self) -> (Option<Logger>, Option<Logger>) {
         (self.stdout.clone(), self.stderr.clone())
     }
-}
-
-impl<'a> TryFrom<&'a ArgMatches<'a>> for Loggers {
-    type Error = Error;
-
-    fn try_from(matches: &'a ArgMatches<'a>) -> Result<Self, Error> {
-        let level = match matches.occurrences_of("v") {
-            0 => Level::Warning,
-            1 => Level::Info,
-            2 => Level::Debug,
-            3 | _ => Level::Trace,
-        };
 
+    /// Create the stdout and stderr loggers, with the stdout logger filtered at the given level.
+    pub fn new(level: Level) -> Self {
         let dm_env = Runtime::env();
 
         let stdout_decorator = TermDecorator::new().stdout().build();
@@ -60,9 +51,38 @@ impl<'a> TryFrom<&'a ArgMatches<'a>> for Loggers {
             .fuse();
         let stderr = Logger::root(stderr_async_drain, o!("env" => dm_env.clone()));
 
-        Ok(Self {
+        Self {
             stdout: Some(stdout),
             stderr: Some(stderr),
-        })
+        }
+    }
+}
+
+impl<'a> TryFrom<&'a ArgMatches<'a>> for Loggers {
+    type Error = Error;
+
+    fn try_from(matches: &'a ArgMatches<'a>) -> Result<Self, Error> {
+        let level = match matches.occurrences_of("v") {
+            0 => Level::Warning,
+            1 => Level::Info,
+            2 => Level::Debug,
+            3 | _ => Level::Trace,
+        };
+
+        Ok(Self::new(level))
+    }
+}
+
+impl<'a> TryFrom<&'a Runtime> for Loggers {
+    type Error = Error;
+
+    /// The stdout log level is taken from `log_level`, and defaults to `warning`.
+    fn try_from(runtime: &'a Runtime) -> Result<Self, Error> {
+        let level = match runtime.log_level() {
+            Some(log_level) => Level::from_str(log_level).map_err(|_| InvalidRuntimeConfig)?,
+            None => Level::Warning,
+        };
+
+        Ok(Self::new(level))
     }
 }
@@ -7,13 +7,12 @@
 // modified, or distributed except according to those terms.
 
 //! HTTP request single header matching
-use crate::config::{self, HeaderPattern, Request as RequestConfig};
+use crate::config::{self, HeaderPattern, Pattern, Request as RequestConfig};
 use crate::error::Error;
-use crate::matcher::{self, RequestMatch, Slogger};
-use cached::{cached_key_result, UnboundCache};
+use crate::matcher::near_miss::{actual_header, actual_header_pattern};
+use crate::matcher::{self, Criterion, RequestMatch, Slogger};
 use http::Request;
 use libeither::Either;
-use regex::Regex;
 use slog::{trace, Logger};
 use slog_try::try_trace;
 use std::fmt;
@@ -79,6 +78,23 @@ impl RequestMatch for ExactMatch {
             Ok(None)
         }
     }
+
+    fn explain(
+        &self,
+        request: &Request<()>,
+        request_config: &config::Request,
+    ) -> Result<Option<Criterion>, Error> {
+        Ok(self.is_match(request, request_config)?.and_then(|matched| {
+            request_config.header().as_ref().map(|header| {
+                Criterion::expected(
+                    matched,
+                    format!("header {}", header.key()),
+                    header.value(),
+                    actual_header(request, header.key()),
+                )
+            })
+        }))
+    }
 }
 
 /// Pattern match a header
@@ -92,7 +108,7 @@ impl PatternMatch {
     fn is_match_either(
         &self,
         actual: &str,
-        either: &Either<String, String>,
+        either: &Either<String, Pattern>,
         case_insensitive: bool,
     ) -> bool {
         if let Ok(expected) = either.left_ref() {
@@ -103,12 +119,7 @@ impl PatternMatch {
             }
         } else if let Ok(expected) = either.right_ref() {
             try_trace!(self.stdout, "Checking {} against {}", actual, expected);
-            if let Ok(regex) = generate_regex(expected) {
-                try_trace!(self.stdout, "Regex: {:?}", regex);
-                regex.is_match(actual)
-            } else {
-                false
-            }
+            expected.is_match(actual)
         } else {
             false
         }
@@ -136,19 +147,6 @@ impl Slogger for PatternMatch {
     }
 }
 
-cached_key_result! {
-    REGEX: UnboundCache<String, Regex> = UnboundCache::new();
-    Key = { header_pattern.to_string() };
-    fn generate_regex(header_pattern: &str) -> Result<Regex, String> = {
-        let regex_result = Regex::new(header_pattern);
-
-        match regex_result {
-            Ok(regex) => Ok(regex),
-            Err(e) => Err(e.to_string()),
-        }
-    }
-}
-
 impl RequestMatch for PatternMatch {
     fn is_match(
         &self,
@@ -190,6 +188,26 @@ impl RequestMatch for PatternMatch {
             Ok(None)
         }
     }
+
+    fn explain(
+        &self,
+        request: &Request<()>,
+        request_config: &RequestConfig,
+    ) -> Result<Option<Criterion>, Error> {
+        Ok(self.is_match(request, request_config)?.and_then(|matched| {
+            request_config
+                .header_pattern()
+                .as_ref()
+                .map(|header_pattern| {
+                    Criterion::expected(
+                        matched,
+                        "header pattern",
+                        header_pattern,
+                        actual_header_pattern(request, header_pattern),
+                    )
+                })
+        }))
+    }
 }
 
 impl fmt::Display for PatternMatch {
@@ -9,11 +9,10 @@
 //! HTTP request headers matching
 use crate::config;
 use crate::error::Error;
-use crate::matcher::{self, RequestMatch, Slogger};
-use cached::{cached_key_result, UnboundCache};
+use crate::matcher::near_miss::{actual_header, actual_header_pattern};
+use crate::matcher::{self, Criterion, RequestMatch, Slogger};
 use http::Request;
 use libeither::Either;
-use regex::Regex;
 use slog::{trace, Logger};
 use slog_try::try_trace;
 use std::fmt;
@@ -82,6 +81,35 @@ impl RequestMatch for ExactMatch {
             ))
         }
     }
+
+    fn explain(
+        &self,
+        request: &Request<()>,
+        request_config: &config::Request,
+    ) -> Result<Option<Criterion>, Error> {
+        Ok(self.is_match(request, request_config)?.map(|matched| {
+            let headers = request_config.headers().iter();
+            if matched {
+                let expected: Vec<String> = headers.map(|header| header.to_string()).collect();
+                Criterion::new(true, format!("headers matched '{}'", expected.join(", ")))
+            } else {
+                let failed: Vec<String> = headers
+                    .filter(|header| self.actual_has_match(request, header) == Some(false))
+                    .map(|header| {
+                        Criterion::expected(
+                            false,
+                            format!("header {}", header.key()),
+                            header.value(),
+                            actual_header(request, header.key()),
+                        )
+                        .description()
+                        .clone()
+                    })
+                    .collect();
+                Criterion::new(false, failed.join(", "))
+            }
+        }))
+    }
 }
 
 /// Pattern match all headers on an HTTP request.
@@ -95,7 +123,7 @@ impl PatternMatch {
     fn is_match_either(
         &self,
         actual: &str,
-        either: &Either<String, String>,
+        either: &Either<String, config::Pattern>,
         case_insensitive: bool,
     ) -> bool {
         if let Ok(expected) = either.left_ref() {
@@ -105,11 +133,7 @@ impl PatternMatch {
                 actual == expected
             }
         } else if let Ok(expected) = either.right_ref() {
-            if let Ok(regex) = generate_regex(expected) {
-                regex.is_match(actual)
-            } else {
-                false
-            }
+            expected.is_match(actual)
         } else {
             false
         }
@@ -125,6 +149,27 @@ impl PatternMatch {
                 && self.is_match_either(actual.1, expected.value(), false),
         )
     }
+
+    fn pattern_has_match(
+        &self,
+        request: &Request<()>,
+        header_pattern: &config::HeaderPattern,
+    ) -> bool {
+        // Loop through the actual headers looking for one match.
+        let matched_header: Vec<bool> = request
+            .headers()
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.to_str()))
+            .filter_map(|(key, result)| match result {
+                Ok(value) => Some((key, value)),
+                Err(_) => None,
+            })
+            .filter_map(|actual_header| self.is_header_match(&actual_header, header_pattern))
+            .filter(|x| *x)
+            .collect();
+
+        matched_header.len() == 1 && matched_header[0]
+    }
 }
 
 impl Slogger for PatternMatch {
@@ -147,19 +192,6 @@ impl fmt::Display for PatternMatch {
     }
 }
 
-cached_key_result! {
-    REGEX: UnboundCache<String, Regex> = UnboundCache::new();
-    Key = { header_pattern.to_string() };
-    fn generate_regex(header_pattern: &str) -> Result<Regex, String> = {
-        let regex_result = Regex::new(header_pattern);
-
-        match regex_result {
-            Ok(regex) => Ok(regex),
-            Err(e) => Err(e.to_string()),
-        }
-    }
-}
-
 impl RequestMatch for PatternMatch {
     fn is_match(
         &self,
@@ -177,25 +209,7 @@ impl RequestMatch for PatternMatch {
             let headers_pattern_match: Vec<bool> = request_config
                 .headers_pattern()
                 .iter()
-                .map(|header_pattern| {
-                    // For every header pattern, loop through the actual
-                    // headers looking for one match.
-                    let matched_header: Vec<bool> = request
-                        .headers()
-                        .iter()
-                        .map(|(key, value)| (key.as_str(), value.to_str()))
-                        .filter_map(|(key, result)| match result {
-                            Ok(value) => Some((key, value)),
-                            Err(_) => None,
-                        })
-                        .filter_map(|actual_header| {
-                            self.is_header_match(&actual_header, header_pattern)
-                        })
-                        .filter(|x| *x)
-                        .collect();
-
-                    matched_header.len() == 1 && matched_header[0]
-                })
+                .map(|header_pattern| self.pattern_has_match(request, header_pattern))
                 .collect();
 
             let all_header_patterns_match =
@@ -204,4 +218,36 @@ impl RequestMatch for PatternMatch {
             Ok(Some(all_header_patterns_match))
         }
     }
+
+    fn explain(
+        &self,
+        request: &Request<()>,
+        request_config: &config::Request,
+    ) -> Result<Option<Criterion>, Error> {
+        Ok(self.is_match(request, request_config)?.map(|matched| {
+            let headers_pattern = request_config.headers_pattern().iter();
+            if matched {
+                let expected: Vec<String> = headers_pattern.map(|hp| hp.to_string()).collect();
+                Criterion::new(
+                    true,
+                    format!("header patterns matched '{}'", expected.join(", ")),
+                )
+            } else {
+                let failed: Vec<String> = headers_pattern
+                    .filter(|header_pattern| !self.pattern_has_match(request, header_pattern))
+                    .map(|header_pattern| {
+                        Criterion::expected(
+                            false,
+                            "header pattern",
+                            header_pattern,
+                            actual_header_pattern(request, header_pattern),
+                        )
+                        .description()
+                        .clone()
+                    })
+                    .collect();
+                Criterion::new(false, failed.join(", "))
+            }
+        }))
+    }
 }
@@ -9,10 +9,8 @@
 //! HTTP request method matching
 use crate::config::{self, Request as RequestConfig};
 use crate::error::Error;
-use crate::matcher::{RequestMatch, Slogger};
-use cached::{cached_key_result, UnboundCache};
+use crate::matcher::{Criterion, RequestMatch, Slogger};
 use http::Request;
-use regex::Regex;
 use slog::{trace, Logger};
 use slog_try::try_trace;
 use std::fmt;
@@ -57,6 +55,19 @@ impl RequestMatch for ExactMatch {
             Ok(None)
         }
     }
+
+    fn explain(
+        &self,
+        request: &Request<()>,
+        request_config: &config::Request,
+    ) -> Result<Option<Criterion>, Error> {
+        Ok(self.is_match(request, request_config)?.and_then(|matched| {
+            request_config
+                .method()
+                .as_ref()
+                .map(|method| Criterion::expected(matched, "method", method, request.method()))
+        }))
+    }
 }
 
 impl fmt::Display for ExactMatch {
@@ -86,19 +97,6 @@ impl Slogger for PatternMatch {
     }
 }
 
-cached_key_result! {
-    REGEX: UnboundCache<String, Regex> = UnboundCache::new();
-    Key = { method_pattern.to_string() };
-    fn generate_regex(method_pattern: &str) -> Result<Regex, String> = {
-        let regex_result = Regex::new(method_pattern);
-
-        match regex_result {
-            Ok(regex) => Ok(regex),
-            Err(e) => Err(e.to_string()),
-        }
-    }
-}
-
 impl RequestMatch for PatternMatch {
     fn is_match(
         &self,
@@ -113,16 +111,27 @@ impl RequestMatch for PatternMatch {
                 method,
                 method_pattern
             );
-            if let Ok(regex) = generate_regex(method_pattern) {
-                Ok(Some(regex.is_match(method)))
-            } else {
-                Ok(Some(false))
-            }
+            Ok(Some(method_pattern.is_match(method)))
         } else {
             try_trace!(self.stdout, "Pattern Match (Method) - No check performed");
             Ok(None)
         }
     }
+
+    fn explain(
+        &self,
+        request: &Request<()>,
+        request_config: &RequestConfig,
+    ) -> Result<Option<Criterion>, Error> {
+        Ok(self.is_match(request, request_config)?.and_then(|matched| {
+            request_config
+                .method_pattern()
+                .as_ref()
+                .map(|method_pattern| {
+                    Criterion::expected(matched, "method pattern", method_pattern, request.method())
+                })
+        }))
+    }
 }
 
 impl fmt::Display for PatternMatch {
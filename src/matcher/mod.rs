@@ -18,6 +18,7 @@ use http::Request;
 use slog::{trace, Logger};
 use slog_try::try_trace;
 use std::fmt;
+use std::sync::Arc;
 
 #[cfg(feature = "header")]
 crate mod header;
@@ -25,6 +26,7 @@ crate mod header;
 crate mod headers;
 #[cfg(feature = "method")]
 crate mod method;
+crate mod near_miss;
 #[cfg(feature = "url")]
 crate mod url;
 
@@ -40,6 +42,7 @@ pub use self::headers::PatternMatch as PatternMatchHeaders;
 pub use self::method::ExactMatch as ExactMatchMethod;
 #[cfg(all(feature = "pattern_match", feature = "method"))]
 pub use self::method::PatternMatch as PatternMatchMethod;
+pub use self::near_miss::{Criterion, NearMiss};
 #[cfg(all(feature = "exact_match", feature = "url"))]
 pub use self::url::ExactMatch as ExactMatchUrl;
 #[cfg(all(feature = "pattern_match", feature = "url"))]
@@ -203,7 +206,7 @@ pub trait Slogger {
 }
 
 /// A request matcher
-pub trait RequestMatch: fmt::Debug + fmt::Display {
+pub trait RequestMatch: fmt::Debug + fmt::Display + Send + Sync {
     /// Does the incoming request match the request configuration from a mapping.
     ///
     /// If the matcher has configuration, then `is_match` must return `Some(bool)`.
@@ -213,11 +216,31 @@ pub trait RequestMatch: fmt::Debug + fmt::Display {
         request: &Request<()>,
         request_config: &RequestConfig,
     ) -> Result<Option<bool>, Error>;
+
+    /// Explain the result of matching the incoming request against the request configuration,
+    /// i.e. `url expected '/a' got '/b'`.  This backs the near-miss diagnostics for requests
+    /// that match no mapping.
+    ///
+    /// As with `is_match`, a matcher without configuration must return `None`.  By default the
+    /// result is described with the `Display` of the matcher.
+    fn explain(
+        &self,
+        request: &Request<()>,
+        request_config: &RequestConfig,
+    ) -> Result<Option<Criterion>, Error> {
+        Ok(self
+            .is_match(request, request_config)?
+            .map(|matched| Criterion::new(matched, self.to_string())))
+    }
 }
 
 /// Try to match an incoming request to a mapping.
+///
+/// A `Matcher` holds no per-request state, so one can be built up front and shared.
 #[allow(box_pointers)]
 pub struct Matcher {
+    /// The enabled request matching.
+    enabled: Enabled,
     /// The matchers setup for request matching.
     matchers: Vec<Box<dyn RequestMatch>>,
     /// stdout slog logger
@@ -318,6 +341,7 @@ impl Matcher {
     /// Create a new `Matcher`
     pub fn new(enabled: Enabled, stdout: Option<Logger>, stderr: Option<Logger>) -> Self {
         let mut matcher = Self {
+            enabled,
             matchers: vec![],
             stdout,
             stderr,
@@ -342,11 +366,28 @@ impl Matcher {
     }
 
     /// Get a mapping that matches the given request.
-    pub fn get_match(&self, request: &Request<()>, mappings: &Mappings) -> Result<Mapping, Error> {
+    ///
+    /// If more than one mapping matches, the first in the `Mapping` order wins: the lowest
+    /// priority, then the most specific, then the lowest id.
+    ///
+    /// Only the mappings that could match are checked.  The mappings are looked up by method and
+    /// exact url, and by url pattern, as far as the enabled matchers check those.
+    pub fn get_match(
+        &self,
+        request: &Request<()>,
+        mappings: &Mappings,
+    ) -> Result<Arc<Mapping>, Error> {
+        let method = request.method().as_str();
+        let path = request.uri().path();
+
         mappings
-            .inner()
-            .iter()
-            .inspect(|(_uuid, mapping)| {
+            .candidates(
+                self.lookup(Enabled::exact_method(), method),
+                self.lookup(Enabled::exact_url(), path),
+                self.lookup(Enabled::pattern_url(), path),
+            )
+            .into_iter()
+            .inspect(|mapping| {
                 try_trace!(self.stdout, "");
                 try_trace!(
                     self.stdout,
@@ -355,12 +396,53 @@ impl Matcher {
                     80
                 );
             })
-            .filter_map(|(_uuid, mapping)| self.is_match(request, mapping))
+            .filter(|mapping| self.is_match(request, mapping))
             .min()
+            .cloned()
             .ok_or_else(|| MappingNotFound)
     }
 
-    fn is_match(&self, request: &Request<()>, mapping: &Mapping) -> Option<Mapping> {
+    /// Use the given part of the request for the mapping lookup, if the matcher checks it.
+    fn lookup<'a>(&self, flag: Enabled, value: &'a str) -> Option<&'a str> {
+        if !flag.is_empty() && self.enabled.contains(flag) {
+            Some(value)
+        } else {
+            None
+        }
+    }
+
+    /// Find the mappings that came closest to matching the given request, closest first.
+    ///
+    /// Mappings are ranked by the number of criteria that passed, then by the fewest that
+    /// failed, then by priority, name, and id.  At most `limit` near misses are returned.
+    pub fn near_misses(
+        &self,
+        request: &Request<()>,
+        mappings: &Mappings,
+        limit: usize,
+    ) -> Vec<NearMiss> {
+        let mut near_misses: Vec<NearMiss> = mappings
+            .inner()
+            .iter()
+            .map(|(uuid, mapping)| {
+                let criteria = self
+                    .matchers
+                    .iter()
+                    .map(|matcher| matcher.explain(request, mapping.request()))
+                    .filter_map(|res| res.ok())
+                    .filter_map(|x| x)
+                    .collect::<Vec<Criterion>>();
+                NearMiss::new(*uuid, mapping.name(), *mapping.priority(), criteria)
+            })
+            .filter(|near_miss| !near_miss.passed().is_empty() || !near_miss.failed().is_empty())
+            .collect();
+
+        near_misses.sort_by(NearMiss::closeness);
+        near_misses.truncate(limit);
+        near_misses
+    }
+
+    fn is_match(&self, request: &Request<()>, mapping: &Mapping) -> bool {
         let matches = self
             .matchers
             .iter()
@@ -379,11 +461,7 @@ impl Matcher {
         try_trace!(self.stdout, "Matches: {:?}, All: {}", matches, all_true);
 
         // Is the remaining list non-empty and all true?
-        if !matches.is_empty() && all_true {
-            Some(mapping.clone())
-        } else {
-            None
-        }
+        !matches.is_empty() && all_true
     }
 }
 
@@ -406,7 +484,9 @@ mod test {
     use super::Matcher;
     use crate::config::files::test::test_files;
     use crate::config::mappings::test::test_mappings;
-    use crate::matcher::Enabled;
+    use crate::config::pattern::test::pattern;
+    use crate::config::{Mapping, Mappings, Request as RequestConfig, UrlOptions};
+    use crate::matcher::{Enabled, PatternMatchUrl, RequestMatch};
     use http::request::Builder;
     use http::Request;
     // use slog::{o, Drain};
@@ -445,7 +525,7 @@ mod test {
     }
 
     #[allow(box_pointers)]
-    fn check_request(enabled: Enabled, request_builder: &mut Builder, priority: u8, name: &str) {
+    fn check_request(enabled: Enabled, request_builder: &mut Builder, priority: u32, name: &str) {
         let mappings = test_mappings().expect("Unable to setup mappings!");
         // let decorator = slog_term::PlainDecorator::new(std::io::stderr());
         // let drain = slog_term::CompactFormat::new(decorator).build().fuse();
@@ -689,4 +769,135 @@ mod test {
             "Mixed Match - Header & URL",
         );
     }
+
+    #[test]
+    fn near_misses() {
+        let mappings = test_mappings().expect("Unable to setup mappings!");
+        let matcher = Matcher::new(
+            Enabled::EXACT_URL | Enabled::EXACT_METHOD | Enabled::EXACT_HEADER,
+            None,
+            None,
+        );
+
+        if let Ok(request) = Request::get("/header-method-url")
+            .header("X-Exact-Match", "wrong")
+            .body(())
+        {
+            assert!(matcher.get_match(&request, &mappings).is_err());
+            let near_misses = matcher.near_misses(&request, &mappings, 3);
+            assert_eq!(near_misses.len(), 3);
+            let closest = &near_misses[0];
+            assert_eq!(closest.name(), "Exact Match - Header, Method, & URL");
+            assert_eq!(closest.passed().len(), 2);
+            assert_eq!(
+                closest.failed(),
+                &vec!["header X-Exact-Match expected 'header-method-url' got 'wrong'".to_string()]
+            );
+        } else {
+            assert!(false, "Unable to build the request to test!");
+        }
+    }
+
+    #[test]
+    fn url_options() {
+        let mut url_options = UrlOptions::default();
+        let _ = url_options
+            .set_case_insensitive(true)
+            .set_ignore_trailing_slash(true)
+            .set_normalize(true);
+        let exact = Mapping::builder()
+            .name("exact")
+            .when(RequestConfig::get("/users/1").with_url_options(url_options))
+            .build();
+        let by_pattern = Mapping::builder()
+            .name("pattern")
+            .when(
+                RequestConfig::default()
+                    .with_url_pattern(pattern("^/orders/[a-z]+$"))
+                    .with_url_options(url_options),
+            )
+            .build();
+        let mut mappings = Mappings::default();
+        assert!(mappings.insert(exact).is_ok());
+        assert!(mappings.insert(by_pattern).is_ok());
+        let matcher = Matcher::new(Enabled::all(), None, None);
+
+        for (uri, name) in &[
+            ("/users/1", "exact"),
+            ("/Users/1/", "exact"),
+            ("/users//%31", "exact"),
+            ("/ORDERS/Open/", "pattern"),
+        ] {
+            match Request::get(*uri).body(()) {
+                Ok(request) => match matcher.get_match(&request, &mappings) {
+                    Ok(mapping) => assert_eq!(mapping.name(), name),
+                    Err(e) => assert!(false, format!("{}: {}", uri, e)),
+                },
+                Err(e) => assert!(false, e.to_string()),
+            }
+        }
+
+        match Request::get("/users/2").body(()) {
+            Ok(request) => assert!(matcher.get_match(&request, &mappings).is_err()),
+            Err(e) => assert!(false, e.to_string()),
+        }
+    }
+
+    #[test]
+    fn url_options_deserialized() {
+        // A deserialized request config is prepared as it is deserialized, without being added
+        // to `config::Mappings`.
+        let request_config: RequestConfig = toml::from_str(
+            r#"url_pattern = "^/Orders/[A-Z]+$"
+url_options = { case_insensitive = true, ignore_trailing_slash = true }"#,
+        )
+        .expect("Unable to deserialize the request config!");
+        let pattern_match = PatternMatchUrl::default();
+
+        match Request::get("/orders/open").body(()) {
+            Ok(request) => match pattern_match.is_match(&request, &request_config) {
+                Ok(matched) => assert_eq!(matched, Some(true)),
+                Err(e) => assert!(false, e.to_string()),
+            },
+            Err(e) => assert!(false, e.to_string()),
+        }
+
+        match Request::get("/USERS/Open/").body(()) {
+            Ok(request) => match pattern_match.explain(&request, &request_config) {
+                Ok(Some(criterion)) => assert_eq!(
+                    criterion.description(),
+                    "url pattern expected '^/Orders/[A-Z]+$' got '/users/open'"
+                ),
+                Ok(None) => assert!(false, "Expected a url pattern criterion!"),
+                Err(e) => assert!(false, e.to_string()),
+            },
+            Err(e) => assert!(false, e.to_string()),
+        }
+    }
+
+    #[test]
+    fn url_with_query() {
+        let with_query = Mapping::builder()
+            .name("page 1")
+            .when(RequestConfig::get("/users?page=1"))
+            .build();
+        let mut mappings = Mappings::default();
+        assert!(mappings.insert(with_query).is_ok());
+        let matcher = Matcher::new(Enabled::all(), None, None);
+
+        match Request::get("/users?page=1").body(()) {
+            Ok(request) => match matcher.get_match(&request, &mappings) {
+                Ok(mapping) => assert_eq!(mapping.name(), "page 1"),
+                Err(e) => assert!(false, e.to_string()),
+            },
+            Err(e) => assert!(false, e.to_string()),
+        }
+
+        for uri in &["/users?page=2", "/users"] {
+            match Request::get(*uri).body(()) {
+                Ok(request) => assert!(matcher.get_match(&request, &mappings).is_err()),
+                Err(e) => assert!(false, e.to_string()),
+            }
+        }
+    }
 }
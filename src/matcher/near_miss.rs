@@ -0,0 +1,236 @@
+// Copyright (c) 2018 libdeadmock developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Near-miss diagnostics for requests that match no mapping.
+use crate::config::HeaderPattern;
+use getset::Getters;
+use http::Request;
+use serde_derive::Serialize;
+use std::cmp::Ordering;
+use std::fmt;
+use uuid::Uuid;
+
+/// The result of one `RequestMatch` criterion against a mapping.
+#[derive(Clone, Debug, Eq, Getters, PartialEq)]
+pub struct Criterion {
+    /// Did the criterion pass?
+    #[get = "pub"]
+    matched: bool,
+    /// A description of what was expected, and what was found.
+    #[get = "pub"]
+    description: String,
+}
+
+impl Criterion {
+    /// Create a new criterion result.
+    pub fn new<D: Into<String>>(matched: bool, description: D) -> Self {
+        Self {
+            matched,
+            description: description.into(),
+        }
+    }
+
+    /// Describe the criterion as `<what> matched '<expected>'`, or
+    /// `<what> expected '<expected>' got '<actual>'`.
+    pub fn expected<W, E, A>(matched: bool, what: W, expected: E, actual: A) -> Self
+    where
+        W: fmt::Display,
+        E: fmt::Display,
+        A: fmt::Display,
+    {
+        if matched {
+            Self::new(true, format!("{} matched '{}'", what, expected))
+        } else {
+            Self::new(
+                false,
+                format!("{} expected '{}' got '{}'", what, expected, actual),
+            )
+        }
+    }
+}
+
+/// A mapping that came close to matching an unmatched request.
+#[derive(Clone, Debug, Eq, Getters, PartialEq, Serialize)]
+pub struct NearMiss {
+    /// The mapping id.
+    #[get = "pub"]
+    id: Uuid,
+    /// The mapping name.
+    #[get = "pub"]
+    name: String,
+    /// The mapping priority.
+    #[get = "pub"]
+    priority: u32,
+    /// The descriptions of the criteria that passed.
+    #[get = "pub"]
+    passed: Vec<String>,
+    /// The descriptions of the criteria that failed.
+    #[get = "pub"]
+    failed: Vec<String>,
+}
+
+impl NearMiss {
+    crate fn new(id: Uuid, name: &str, priority: u32, criteria: Vec<Criterion>) -> Self {
+        let (passed, failed): (Vec<Criterion>, Vec<Criterion>) = criteria
+            .into_iter()
+            .partition(|criterion| criterion.matched);
+
+        Self {
+            id,
+            name: name.to_string(),
+            priority,
+            passed: passed.into_iter().map(|c| c.description).collect(),
+            failed: failed.into_iter().map(|c| c.description).collect(),
+        }
+    }
+
+    /// Closer misses sort first: the most criteria passed, then the fewest failed.  Ties are
+    /// broken by priority, then name, then id, so the order is the same on every run.
+    crate fn closeness(&self, other: &Self) -> Ordering {
+        other
+            .passed
+            .len()
+            .cmp(&self.passed.len())
+            .then_with(|| self.failed.len().cmp(&other.failed.len()))
+            .then_with(|| self.priority.cmp(&other.priority))
+            .then_with(|| self.name.cmp(&other.name))
+            .then_with(|| self.id.cmp(&other.id))
+    }
+}
+
+impl fmt::Display for NearMiss {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let criteria: Vec<&str> = self
+            .passed
+            .iter()
+            .chain(self.failed.iter())
+            .map(|c| &c[..])
+            .collect();
+        write!(f, "'{}' ({}): {}", self.name, self.id, criteria.join(", "))
+    }
+}
+
+/// Headers whose values are never shown in near-miss descriptions, as they are logged.
+const SENSITIVE_HEADERS: &[&str] = &["authorization", "proxy-authorization", "cookie"];
+
+/// The values of the named header on the request, for near-miss descriptions.
+///
+/// The values of credential headers, and of any value marked sensitive, are redacted.
+crate fn actual_header<T>(request: &Request<T>, key: &str) -> String {
+    let values = request.headers().get_all(key);
+    let sensitive = SENSITIVE_HEADERS
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(key))
+        || values.iter().any(|value| value.is_sensitive());
+
+    if values.iter().next().is_none() {
+        "<missing>".to_string()
+    } else if sensitive {
+        "<redacted>".to_string()
+    } else {
+        values
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect::<Vec<&str>>()
+            .join(", ")
+    }
+}
+
+/// The values of the header named by the pattern on the request, for near-miss descriptions.
+///
+/// Only a header pattern with an exact key names a header.
+crate fn actual_header_pattern<T>(
+    request: &Request<T>,
+    header_pattern: &HeaderPattern,
+) -> String {
+    if let Ok(key) = header_pattern.key().left_ref() {
+        actual_header(request, key)
+    } else {
+        "<no matching header>".to_string()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{actual_header, Criterion, NearMiss};
+    use http::Request;
+    use uuid::Uuid;
+
+    #[test]
+    fn near_miss() {
+        let near_miss = NearMiss::new(
+            Uuid::nil(),
+            "Users",
+            1,
+            vec![
+                Criterion::expected(false, "header x-foo", "a", "b"),
+                Criterion::expected(true, "url", "/users", "/users"),
+            ],
+        );
+        assert_eq!(
+            near_miss.passed(),
+            &vec!["url matched '/users'".to_string()]
+        );
+        assert_eq!(
+            near_miss.failed(),
+            &vec!["header x-foo expected 'a' got 'b'".to_string()]
+        );
+        assert_eq!(
+            near_miss.to_string(),
+            "'Users' (00000000-0000-0000-0000-000000000000): url matched '/users', header x-foo \
+             expected 'a' got 'b'"
+        );
+
+        let closer = NearMiss::new(
+            Uuid::nil(),
+            "Closer",
+            1,
+            vec![
+                Criterion::new(true, "url"),
+                Criterion::new(true, "method"),
+                Criterion::new(false, "header"),
+            ],
+        );
+        let mut misses = vec![near_miss, closer];
+        misses.sort_by(NearMiss::closeness);
+        assert_eq!(misses[0].name(), "Closer");
+
+        // Equally close misses sort by priority, then name.
+        let tie = |name: &str, priority: u32| {
+            NearMiss::new(
+                Uuid::new_v4(),
+                name,
+                priority,
+                vec![Criterion::new(false, "url")],
+            )
+        };
+        let mut ties = vec![tie("b", 2), tie("c", 1), tie("a", 2)];
+        ties.sort_by(NearMiss::closeness);
+        let names: Vec<&str> = ties.iter().map(|tie| tie.name().as_str()).collect();
+        assert_eq!(names, vec!["c", "a", "b"]);
+    }
+
+    #[test]
+    fn header_values() {
+        if let Ok(request) = Request::get("/").header("x-foo", "b").body(()) {
+            assert_eq!(actual_header(&request, "x-foo"), "b");
+            assert_eq!(actual_header(&request, "x-bar"), "<missing>");
+        } else {
+            assert!(false, "Unable to build the request to test!");
+        }
+
+        if let Ok(request) = Request::get("/")
+            .header("Authorization", "Bearer secret")
+            .body(())
+        {
+            assert_eq!(actual_header(&request, "Authorization"), "<redacted>");
+        } else {
+            assert!(false, "Unable to build the request to test!");
+        }
+    }
+}
@@ -9,12 +9,11 @@
 //! HTTP request URL matching
 use crate::config::Request as RequestConfig;
 use crate::error::Error;
-use crate::matcher::{RequestMatch, Slogger};
-use cached::{cached_key_result, UnboundCache};
+use crate::matcher::{Criterion, RequestMatch, Slogger};
 use http::Request;
-use regex::Regex;
 use slog::{trace, Logger};
 use slog_try::try_trace;
+use std::borrow::Cow;
 use std::fmt;
 
 /// Exactly match a url
@@ -45,18 +44,56 @@ impl RequestMatch for ExactMatch {
         request_config: &RequestConfig,
     ) -> Result<Option<bool>, Error> {
         if let Some(url) = request_config.url() {
+            let path = request_path(request, url);
             try_trace!(
                 self.stdout,
                 "Exact Match (URL) - Checking {} against {}",
                 url,
-                request.uri().path()
+                path
             );
-            Ok(Some(request.uri().path() == &url[..]))
+            Ok(Some(match request_config.url_options() {
+                Some(url_options) => url_options.apply(path) == url_options.apply(url),
+                None => path == &url[..],
+            }))
         } else {
             try_trace!(self.stdout, "Exact Match (URL) - No check performed");
             Ok(None)
         }
     }
+
+    fn explain(
+        &self,
+        request: &Request<()>,
+        request_config: &RequestConfig,
+    ) -> Result<Option<Criterion>, Error> {
+        Ok(self.is_match(request, request_config)?.and_then(|matched| {
+            request_config.url().as_ref().map(|url| {
+                Criterion::expected(
+                    matched,
+                    "url",
+                    url,
+                    compared_path(request_config, request_path(request, url)),
+                )
+            })
+        }))
+    }
+}
+
+/// The part of the request uri an exact url is compared with.  A url with a query string is
+/// compared with the path and query, and any other url with just the path.
+fn request_path<'a>(request: &'a Request<()>, url: &str) -> &'a str {
+    match request.uri().path_and_query() {
+        Some(path_and_query) if url.contains('?') => path_and_query.as_str(),
+        _ => request.uri().path(),
+    }
+}
+
+/// The request path as it is compared, i.e. with the url options applied.
+fn compared_path<'a>(request_config: &RequestConfig, path: &'a str) -> Cow<'a, str> {
+    match request_config.url_options() {
+        Some(url_options) => Cow::Owned(url_options.apply(path)),
+        None => Cow::Borrowed(path),
+    }
 }
 
 impl fmt::Display for ExactMatch {
@@ -86,19 +123,6 @@ impl Slogger for PatternMatch {
     }
 }
 
-cached_key_result! {
-    REGEX: UnboundCache<String, Regex> = UnboundCache::new();
-    Key = { path.to_string() };
-    fn generate_regex(path: &str, url_pattern: &str) -> Result<Regex, String> = {
-        let regex_result = Regex::new(url_pattern);
-
-        match regex_result {
-            Ok(regex) => Ok(regex),
-            Err(e) => Err(e.to_string()),
-        }
-    }
-}
-
 impl RequestMatch for PatternMatch {
     fn is_match(
         &self,
@@ -113,16 +137,34 @@ impl RequestMatch for PatternMatch {
                 url_pattern,
                 path
             );
-            if let Ok(regex) = generate_regex(path, url_pattern) {
-                Ok(Some(regex.is_match(path)))
-            } else {
-                Ok(Some(false))
-            }
+            // The pattern was compiled to ignore case, if asked, as the request config was built
+            // or deserialized.
+            Ok(Some(match request_config.url_options() {
+                Some(url_options) => url_pattern.is_match(&url_options.apply(path)),
+                None => url_pattern.is_match(path),
+            }))
         } else {
             try_trace!(self.stdout, "Patten Match (URL) - No check performed");
             Ok(None)
         }
     }
+
+    fn explain(
+        &self,
+        request: &Request<()>,
+        request_config: &RequestConfig,
+    ) -> Result<Option<Criterion>, Error> {
+        Ok(self.is_match(request, request_config)?.and_then(|matched| {
+            request_config.url_pattern().as_ref().map(|url_pattern| {
+                Criterion::expected(
+                    matched,
+                    "url pattern",
+                    url_pattern,
+                    compared_path(request_config, request.uri().path()),
+                )
+            })
+        }))
+    }
 }
 
 impl fmt::Display for PatternMatch {
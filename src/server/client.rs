@@ -0,0 +1,196 @@
+// Copyright (c) 2018 libdeadmock developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Pooled upstream clients for proxied requests.
+use crate::config;
+use crate::error::Error::{self, InvalidProxyUri, UpstreamClient};
+use hyper::client::HttpConnector;
+use hyper::Client;
+use hyper_proxy::{Intercept, Proxy, ProxyConnector};
+use hyper_tls::HttpsConnector;
+use native_tls::TlsConnector;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+use typed_headers::Credentials;
+
+/// A client for plain HTTP upstreams.
+crate type HttpClient = Client<HttpConnector, hyper::Body>;
+/// A client for HTTPS upstreams.
+crate type HttpsClient = Client<HttpsConnector<HttpConnector>, hyper::Body>;
+/// A client for upstreams reached through the configured proxy.
+crate type ProxyClient = Client<ProxyConnector<HttpConnector>, hyper::Body>;
+
+/// The connector settings that require a distinct client.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct ClientKey {
+    connect_timeout_ms: Option<u64>,
+    proxy_url: Option<String>,
+    client_tls: Option<config::ClientTls>,
+}
+
+impl ClientKey {
+    fn new(upstream_config: &config::Upstream, client_tls: Option<&config::ClientTls>) -> Self {
+        Self {
+            connect_timeout_ms: *upstream_config.connect_timeout_ms(),
+            proxy_url: None,
+            client_tls: client_tls.cloned(),
+        }
+    }
+
+    fn proxy(
+        upstream_config: &config::Upstream,
+        proxy_url: &str,
+        client_tls: Option<&config::ClientTls>,
+    ) -> Self {
+        Self {
+            connect_timeout_ms: *upstream_config.connect_timeout_ms(),
+            proxy_url: Some(proxy_url.to_string()),
+            client_tls: client_tls.cloned(),
+        }
+    }
+}
+
+/// Long-lived upstream clients, shared across requests.
+///
+/// Each client owns a connection pool, so a client is created once for each distinct connector
+/// configuration and reused from then on.
+#[derive(Clone, Debug, Default)]
+crate struct Clients {
+    http: Arc<Mutex<HashMap<ClientKey, HttpClient>>>,
+    https: Arc<Mutex<HashMap<ClientKey, HttpsClient>>>,
+    proxy: Arc<Mutex<HashMap<ClientKey, ProxyClient>>>,
+}
+
+/// Get the client for the given key, creating it if necessary.
+///
+/// A failure to create the client is an `UpstreamClient` error, so it is answered with a 502.
+fn get_or_create<K, C, F>(clients: &Mutex<HashMap<K, C>>, key: K, create: F) -> Result<C, Error>
+where
+    K: Eq + Hash,
+    C: Clone,
+    F: FnOnce() -> Result<C, Error>,
+{
+    let mut clients = match clients.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+
+    if let Some(client) = clients.get(&key) {
+        return Ok(client.clone());
+    }
+
+    let client = create().map_err(|e| UpstreamClient(Box::new(e)))?;
+    let _ = clients.insert(key, client.clone());
+    Ok(client)
+}
+
+fn http_connector(upstream_config: &config::Upstream) -> HttpConnector {
+    let mut connector = HttpConnector::new(4);
+    connector.set_connect_timeout(upstream_config.connect_timeout());
+    connector
+}
+
+fn tls_connector(client_tls: Option<&config::ClientTls>) -> Result<TlsConnector, Error> {
+    match client_tls {
+        Some(client_tls) => client_tls.connector(),
+        None => Ok(TlsConnector::new()?),
+    }
+}
+
+impl Clients {
+    /// Get the client for plain HTTP upstreams.
+    crate fn http(&self, upstream_config: &config::Upstream) -> Result<HttpClient, Error> {
+        get_or_create(&self.http, ClientKey::new(upstream_config, None), || {
+            Ok(Client::builder()
+                .set_host(true)
+                .build(http_connector(upstream_config)))
+        })
+    }
+
+    /// Get the client for HTTPS upstreams, using the given client TLS settings.
+    crate fn https(
+        &self,
+        upstream_config: &config::Upstream,
+        client_tls: Option<&config::ClientTls>,
+    ) -> Result<HttpsClient, Error> {
+        get_or_create(
+            &self.https,
+            ClientKey::new(upstream_config, client_tls),
+            || {
+                let mut connector = http_connector(upstream_config);
+                connector.enforce_http(false);
+                let tls_connector = tls_connector(client_tls)?;
+                Ok(Client::builder()
+                    .set_host(true)
+                    .build(HttpsConnector::from((connector, tls_connector))))
+            },
+        )
+    }
+
+    /// Get the client for upstreams reached through the given proxy url.
+    ///
+    /// The proxy credentials are taken from the proxy configuration, and the client TLS settings
+    /// apply to `https` upstreams tunneled through the proxy.
+    crate fn proxy(
+        &self,
+        proxy_url: &str,
+        proxy_config: &config::Proxy,
+        upstream_config: &config::Upstream,
+        client_tls: Option<&config::ClientTls>,
+    ) -> Result<ProxyClient, Error> {
+        get_or_create(
+            &self.proxy,
+            ClientKey::proxy(upstream_config, proxy_url, client_tls),
+            || {
+                let proxy_uri = proxy_url.parse().map_err(InvalidProxyUri)?;
+                let mut proxy = Proxy::new(Intercept::All, proxy_uri);
+                if let Some(username) = proxy_config.proxy_username() {
+                    if let Some(password) = proxy_config.proxy_password() {
+                        if let Ok(creds) = Credentials::basic(username, password) {
+                            proxy.set_authorization(creds);
+                        }
+                    }
+                }
+
+                let mut proxy_connector =
+                    ProxyConnector::from_proxy_unsecured(http_connector(upstream_config), proxy);
+                proxy_connector.set_tls(Some(tls_connector(client_tls)?));
+                Ok(Client::builder().set_host(true).build(proxy_connector))
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Clients;
+    use crate::config;
+    use crate::error::Error::UpstreamClient;
+    use http::StatusCode;
+
+    #[test]
+    fn client_failure_is_bad_gateway() {
+        let clients = Clients::default();
+        match clients.proxy(
+            "not a proxy url",
+            &config::Proxy::default(),
+            &config::Upstream::default(),
+            None,
+        ) {
+            Ok(_) => assert!(false, "Not expected to succeed!"),
+            Err(e) => {
+                assert_eq!(e.status_code(), StatusCode::BAD_GATEWAY);
+                match e {
+                    UpstreamClient(_) => {}
+                    _ => assert!(false, "Expected an upstream client error!"),
+                }
+            }
+        }
+    }
+}
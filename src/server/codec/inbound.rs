@@ -7,16 +7,38 @@
 // modified, or distributed except according to those terms.
 
 //! Codec for decoding inbound HTTP requests.
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use chrono::Utc;
 use http::header::HeaderValue;
 use http::{Request, Response, Version};
-use std::{fmt, io};
+use std::{fmt, io, str};
 use tokio_io::codec::{Decoder, Encoder};
 
+/// The default maximum request body size, in bytes.
+crate const DEFAULT_MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+
 /// HTTP codec for tokio.
-#[derive(Copy, Clone, Debug)]
-crate struct Http;
+#[derive(Clone, Debug)]
+crate struct Http {
+    /// The largest request body that is read, in bytes.
+    max_body_size: usize,
+    /// The number of bytes of a rejected body still to be skipped.
+    discard: usize,
+}
+
+impl Http {
+    crate fn new(max_body_size: usize) -> Self {
+        Self {
+            max_body_size,
+            discard: 0,
+        }
+    }
+}
+
+/// Marks a request whose body was over the maximum size.  The body is skipped rather than
+/// read, and the request should be answered with a 413.
+#[derive(Clone, Copy, Debug)]
+crate struct BodyTooLarge;
 
 // Right now `write!` on `Vec<u8>` goes through io::Write and is not
 // super speedy, so inline a less-crufty implementation here which
@@ -72,19 +94,42 @@ impl Encoder for Http {
     }
 }
 
+/// Parse the `Content-Length` header value of an inbound request.
+fn content_length(value: &[u8]) -> io::Result<usize> {
+    str::from_utf8(value)
+        .ok()
+        .and_then(|value| value.trim().parse().ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "invalid Content-Length header"))
+}
+
 /// Implementation of decoding an HTTP request from the bytes we've read so far.
 /// This leverages the `httparse` crate to do the actual parsing and then we use
 /// that information to construct an instance of a `http::Request` object,
 /// trying to avoid allocations where possible.
+///
+/// The request body is read according to the `Content-Length` header.
+/// Transfer-encoded (i.e. chunked) request bodies are not supported.
+///
+/// A body over the maximum size is never buffered.  The request is decoded with an empty body
+/// and a `BodyTooLarge` extension, and the body bytes are skipped as they arrive.
 impl Decoder for Http {
-    type Item = Request<()>;
+    type Item = Request<Bytes>;
     type Error = io::Error;
 
-    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Request<()>>> {
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Request<Bytes>>> {
+        if self.discard > 0 {
+            let skip = self.discard.min(src.len());
+            let _ = src.split_to(skip);
+            self.discard -= skip;
+            if self.discard > 0 {
+                return Ok(None);
+            }
+        }
+
         // TODO: we should grow this headers array if parsing fails and asks
         //       for more headers
         let mut headers = [None; 16];
-        let (method, path, version, amt) = {
+        let (method, path, version, amt, body_len) = {
             let mut parsed_headers = [httparse::EMPTY_HEADER; 16];
             let mut r = httparse::Request::new(&mut parsed_headers);
             let status = r.parse(src).map_err(|e| {
@@ -103,7 +148,16 @@ impl Decoder for Http {
                 (start, start + a.len())
             };
 
+            let mut body_len = 0;
             for (i, header) in r.headers.iter().enumerate() {
+                if header.name.eq_ignore_ascii_case("content-length") {
+                    body_len = content_length(header.value)?;
+                } else if header.name.eq_ignore_ascii_case("transfer-encoding") {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "transfer-encoded request bodies are not supported",
+                    ));
+                }
                 let k = toslice(header.name.as_bytes());
                 let v = toslice(header.value);
                 headers[i] = Some((k, v));
@@ -114,6 +168,7 @@ impl Decoder for Http {
                 toslice(r.path.unwrap().as_bytes()),
                 r.version.unwrap(),
                 amt,
+                body_len,
             )
         };
         if version != 1 {
@@ -122,7 +177,21 @@ impl Decoder for Http {
                 "only HTTP/1.1 accepted",
             ));
         }
+        // A body that is too large is skipped rather than waited for, so the request is answered
+        // with a 413 as soon as the head has arrived.
+        let too_large = body_len > self.max_body_size;
+        if !too_large && src.len() < amt + body_len {
+            // Wait until the full body has arrived.
+            src.reserve(amt + body_len - src.len());
+            return Ok(None);
+        }
         let data = src.split_to(amt).freeze();
+        let body = if too_large {
+            self.discard = body_len;
+            Bytes::new()
+        } else {
+            src.split_to(body_len).freeze()
+        };
         let mut request = Request::builder();
         let _ = request.method(&data[method.0..method.1]);
         let _ = request.uri(data.slice(path.0, path.1));
@@ -137,9 +206,44 @@ impl Decoder for Http {
             let _ = request.header(&data[k.0..k.1], value);
         }
 
-        let req = request
-            .body(())
+        let mut req = request
+            .body(body)
             .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        if too_large {
+            let _ = req.extensions_mut().insert(BodyTooLarge);
+        }
         Ok(Some(req))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{BodyTooLarge, Http};
+    use bytes::BytesMut;
+    use tokio_io::codec::Decoder;
+
+    #[test]
+    fn body_too_large() {
+        let mut codec = Http::new(4);
+        let mut src = BytesMut::from(&b"POST /big HTTP/1.1\r\nContent-Length: 10\r\n\r\n01234"[..]);
+
+        match codec.decode(&mut src) {
+            Ok(Some(request)) => {
+                assert!(request.extensions().get::<BodyTooLarge>().is_some());
+                assert!(request.body().is_empty());
+            }
+            _ => assert!(false, "expected the request head"),
+        }
+
+        // The rest of the rejected body is skipped, and the next request is decoded.
+        src.extend_from_slice(b"56789GET /small HTTP/1.1\r\nContent-Length: 2\r\n\r\nok");
+        match codec.decode(&mut src) {
+            Ok(Some(request)) => {
+                assert!(request.extensions().get::<BodyTooLarge>().is_none());
+                assert_eq!(request.uri().path(), "/small");
+                assert_eq!(&request.body()[..], b"ok");
+            }
+            _ => assert!(false, "expected the next request"),
+        }
+    }
+}
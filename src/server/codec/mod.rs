@@ -9,4 +9,4 @@
 //! Codecs used by the async runtime.
 crate mod inbound;
 
-crate use self::inbound::Http;
+crate use self::inbound::{BodyTooLarge, Http, DEFAULT_MAX_BODY_SIZE};
@@ -0,0 +1,135 @@
+// Copyright (c) 2018 libdeadmock developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Call count expectation verification.
+use crate::config::{self, Times};
+use getset::Getters;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use uuid::Uuid;
+
+/// A mapping expectation that was not met.
+#[derive(Clone, Debug, Eq, Getters, Hash, PartialEq)]
+pub struct Unmet {
+    /// The id of the mapping.
+    #[get = "pub"]
+    id: Uuid,
+    /// The name of the mapping.
+    #[get = "pub"]
+    name: String,
+    /// The expected number of matches.
+    #[get = "pub"]
+    expected: Times,
+    /// The actual number of matches.
+    #[get = "pub"]
+    actual: u64,
+}
+
+impl fmt::Display for Unmet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "'{}' ({}): expected {} match(es), got {}",
+            self.name, self.id, self.expected, self.actual
+        )
+    }
+}
+
+/// The number of times each mapping has matched, by mapping id.
+#[derive(Clone, Debug, Default)]
+crate struct Hits {
+    inner: Arc<Mutex<HashMap<Uuid, u64>>>,
+}
+
+impl Hits {
+    /// Count a match of the given mapping.
+    crate fn hit(&self, mapping: &config::Mapping) {
+        if let Some(id) = mapping.id() {
+            let mut hits = match self.inner.lock() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            *hits.entry(*id).or_insert(0) += 1;
+        }
+    }
+
+    /// Check the expectations of the given mappings against the match counts.
+    crate fn verify(&self, mappings: &config::Mappings) -> Vec<Unmet> {
+        let hits = match self.inner.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        let mut unmet: Vec<Unmet> = mappings
+            .inner()
+            .iter()
+            .filter_map(|(id, mapping)| {
+                let expected = (*mapping.expect())?;
+                let actual = hits.get(id).cloned().unwrap_or(0);
+
+                if expected.is_met(actual) {
+                    None
+                } else {
+                    Some(Unmet {
+                        id: *id,
+                        name: mapping.name().clone(),
+                        expected,
+                        actual,
+                    })
+                }
+            })
+            .collect();
+        unmet.sort_by(|left, right| left.name.cmp(&right.name));
+        unmet
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Hits;
+    use crate::config::{Mapping, Mappings, Times};
+
+    #[test]
+    fn verify() {
+        let mut mappings = Mappings::default();
+        let once = Mapping::builder()
+            .name("once")
+            .expect(Times::Exactly(1))
+            .build();
+        let never = Mapping::builder()
+            .name("never")
+            .expect(Times::Never)
+            .build();
+        let any = Mapping::builder().name("any").build();
+        let ids: Vec<_> = vec![once, never, any]
+            .into_iter()
+            .filter_map(|mapping| mappings.insert(mapping).ok())
+            .collect();
+        assert_eq!(ids.len(), 3);
+
+        let hits = Hits::default();
+        let unmet = hits.verify(&mappings);
+        assert_eq!(unmet.len(), 1);
+        assert_eq!(unmet[0].name(), "once");
+        assert_eq!(unmet[0].actual(), &0);
+
+        for id in &ids {
+            if let Some(mapping) = mappings.inner().get(id) {
+                hits.hit(mapping);
+            }
+        }
+        let unmet = hits.verify(&mappings);
+        assert_eq!(unmet.len(), 1);
+        assert_eq!(unmet[0].name(), "never");
+        assert_eq!(
+            unmet[0].to_string(),
+            format!("'never' ({}): expected never match(es), got 1", ids[1])
+        );
+    }
+}
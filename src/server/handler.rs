@@ -8,18 +8,27 @@
 
 //! Request/Response handling for the async runtime.
 use crate::config;
-use crate::error::Error;
-use crate::matcher::{Enabled, Matcher};
+use crate::error::Error::{self, UnmetExpectations, UpstreamCanceled};
+use crate::logging::Loggers;
+use crate::matcher::{Enabled, Matcher, NearMiss};
+use crate::server::client::Clients;
 use crate::server::codec;
+use crate::server::expect::Hits;
 use crate::server::header;
+use crate::server::http2::{self, Preface};
+use crate::server::record::Recorder;
+use crate::server::reload;
 use crate::util::{self, FutResponse};
-use cached::{cached_key_result, UnboundCache};
-use futures::{future, Future, Sink, Stream};
-use http::{Request, Response, StatusCode};
-use hyper::client::HttpConnector;
+use bytes::Bytes;
+use cached::{cached_key_result, Cached, UnboundCache};
+use futures::future::{self, Either};
+use futures::{Future, Sink, Stream};
+use http::header::{
+    HeaderMap, HeaderName, HeaderValue, ACCEPT_ENCODING, CONTENT_LENGTH, DATE, HOST, SERVER,
+};
+use http::{Method, Request, Response, StatusCode, Uri};
 use hyper::{Client, Request as HyperRequest};
-use hyper_proxy::{Intercept, Proxy, ProxyConnector};
-use hyper_tls::HttpsConnector;
+use rustls::Session;
 use slog::Logger;
 use slog::{error, info, trace};
 use slog_try::{try_error, try_info, try_trace};
@@ -27,12 +36,21 @@ use std::fs::File;
 use std::io::{self, BufReader, ErrorKind, Read};
 use std::net::SocketAddr;
 use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Instant;
 use tokio::await;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
 use tokio::prelude::FutureExt;
+use tokio::timer::timeout::Error as TimeoutError;
+use tokio::timer::Delay;
 use tokio_codec::Decoder;
-use typed_headers::Credentials;
+use tokio_rustls::TlsAcceptor;
+use uuid::Uuid;
+
+/// The number of near misses reported for an unmatched request.
+const NEAR_MISS_LIMIT: usize = 3;
 
 /// Request/Response handler for the async runtime.
 #[derive(Clone, Debug)]
@@ -40,10 +58,29 @@ pub struct Handler {
     stdout: Option<Logger>,
     stderr: Option<Logger>,
     proxy_config: config::Proxy,
+    upstream_config: config::Upstream,
+    clients: Clients,
     files_path: PathBuf,
     enabled: Enabled,
-    static_mappings: config::Mappings,
+    matcher: Arc<Matcher>,
+    static_mappings: Arc<RwLock<config::Mappings>>,
     dynamic_mappings: Arc<Mutex<config::Mappings>>,
+    recorder: Option<Recorder>,
+    hits: Hits,
+    fallback: Option<config::Response>,
+    watch_path: Option<PathBuf>,
+    diagnostics: bool,
+    max_body_size: usize,
+    connections: Arc<AtomicUsize>,
+}
+
+/// An open connection, counted against its `Handler` until it is dropped.
+crate struct Connection(Arc<AtomicUsize>);
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        let _ = self.0.fetch_sub(1, Ordering::SeqCst);
+    }
 }
 
 impl Handler {
@@ -58,44 +95,197 @@ impl Handler {
             stdout: None,
             stderr: None,
             proxy_config,
+            upstream_config: config::Upstream::default(),
+            clients: Clients::default(),
             files_path,
             enabled,
-            static_mappings,
+            matcher: Arc::new(Matcher::new(enabled, None, None)),
+            static_mappings: Arc::new(RwLock::new(static_mappings)),
             dynamic_mappings: Arc::new(Mutex::new(config::Mappings::default())),
+            recorder: None,
+            hits: Hits::default(),
+            fallback: None,
+            watch_path: None,
+            diagnostics: false,
+            max_body_size: codec::DEFAULT_MAX_BODY_SIZE,
+            connections: Arc::new(AtomicUsize::new(0)),
         }
     }
 
     /// Add a stdout slog logger to this handler.
     pub fn stdout(mut self, stdout: Option<Logger>) -> Self {
         self.stdout = stdout;
+        self.rebuild_matcher();
         self
     }
 
     /// Add a stderr slog logger to this handler.
     pub fn stderr(mut self, stderr: Option<Logger>) -> Self {
         self.stderr = stderr;
+        self.rebuild_matcher();
+        self
+    }
+
+    /// The matcher is shared by every request, so it is rebuilt when the loggers change.
+    fn rebuild_matcher(&mut self) {
+        self.matcher = Arc::new(Matcher::new(
+            self.enabled,
+            self.stdout.clone(),
+            self.stderr.clone(),
+        ));
+    }
+
+    /// Add a record mode configuration to this handler.
+    ///
+    /// If record mode is enabled, proxied request/response pairs are written out as mappings.
+    pub fn record(mut self, record_config: config::Record) -> Self {
+        self.recorder = if *record_config.enabled() {
+            Some(Recorder::new(record_config))
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Add the global upstream timeout and retry configuration to this handler.
+    ///
+    /// These can be overridden per mapping on `config::Response`.
+    pub fn upstream(mut self, upstream_config: config::Upstream) -> Self {
+        self.upstream_config = upstream_config;
+        self
+    }
+
+    /// Add a fallback proxy base url to this handler.
+    ///
+    /// Requests that match no mapping are proxied to this url rather than answered with a 404.
+    /// The fallback requests go through the configured `config::Proxy`, as any other proxied
+    /// request does.
+    pub fn fallback(mut self, proxy_base_url: Option<String>) -> Self {
+        self.fallback = proxy_base_url.map(config::Response::proxy);
+        self
+    }
+
+    /// Watch the given mappings directory, and the files directory, for changes.
+    ///
+    /// When the server starts, changed mappings are reloaded and swapped in, and changed
+    /// response files are evicted from the response file cache.
+    pub fn watch(mut self, mappings_path: Option<PathBuf>) -> Self {
+        self.watch_path = mappings_path;
+        self
+    }
+
+    /// Include near-miss diagnostics in the body of the 404 response for unmatched requests.
+    ///
+    /// The closest mappings, and which of their criteria passed and failed, are always logged.
+    pub fn diagnostics(mut self, diagnostics: bool) -> Self {
+        self.diagnostics = diagnostics;
+        self
+    }
+
+    /// Set the largest request body that is accepted, in bytes.
+    ///
+    /// Requests with a larger body are answered with a 413, without the body being buffered.
+    pub fn max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
         self
     }
+
+    crate fn body_limit(&self) -> usize {
+        self.max_body_size
+    }
+
+    /// Count a connection as open until the returned guard is dropped.
+    crate fn connection(&self) -> Connection {
+        let _ = self.connections.fetch_add(1, Ordering::SeqCst);
+        Connection(self.connections.clone())
+    }
+
+    /// The number of connections that are open, including those still in the preface or TLS
+    /// handshake.
+    crate fn open_connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+
+    crate fn stdout_log(&self) -> &Option<Logger> {
+        &self.stdout
+    }
+
+    crate fn stderr_log(&self) -> &Option<Logger> {
+        &self.stderr
+    }
+
+    crate fn files_path(&self) -> &PathBuf {
+        &self.files_path
+    }
+
+    /// Add a mapping to the dynamic mappings, returning its id.
+    crate fn add_mapping(&self, mapping: config::Mapping) -> Result<Uuid, Error> {
+        let mut dynamic_mappings = match self.dynamic_mappings.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        dynamic_mappings.insert(mapping)
+    }
+
+    /// Check the mapping expectations against the number of times each mapping has matched.
+    crate fn verify(&self) -> Result<(), Error> {
+        let mut unmet = {
+            let static_mappings = match self.static_mappings.read() {
+                Ok(guard) => guard,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            self.hits.verify(&static_mappings)
+        };
+        let dynamic_mappings = match self.dynamic_mappings.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        unmet.extend(self.hits.verify(&dynamic_mappings));
+
+        if unmet.is_empty() {
+            Ok(())
+        } else {
+            Err(UnmetExpectations(unmet))
+        }
+    }
+
+    /// Replace the static mappings.
+    crate fn swap_static_mappings(&self, mappings: config::Mappings) {
+        let mut static_mappings = match self.static_mappings.write() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        *static_mappings = mappings;
+    }
 }
 
 /// Spawn a task onto the event loop to handle the request.
+///
+/// The stream is either a plain `TcpStream` or a TLS stream over one.
 #[allow(box_pointers)]
-pub fn handle(handler: Handler, stream: TcpStream) {
+pub fn handle<S>(handler: Handler, stream: S)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
     // Frame the socket using the `Http` protocol. This maps the TCP socket
     // to a Stream + Sink of HTTP frames.
     // This splits a single `Stream + Sink` value into two separate handles
     // that can be used independently (even on different tasks or threads).
-    let (tx, rx) = codec::Http.framed(stream).split();
+    let (tx, rx) = codec::Http::new(handler.max_body_size)
+        .framed(stream)
+        .split();
 
     // Clone all the things....
     let response_stderr_1 = handler.stderr.clone();
+    let connection = handler.connection();
 
     // Map all requests into responses and send them back to the client.
     let task = tx
         .send_all(rx.and_then(move |req| {
-            respond(handler.clone(), &req).map_err(|e| io::Error::new(ErrorKind::Other, e))
+            respond(handler.clone(), req).map_err(|e| io::Error::new(ErrorKind::Other, e))
         }))
         .then(move |res| {
+            drop(connection);
             if let Err(e) = res {
                 try_error!(response_stderr_1, "failed to process the request: {}", e);
             }
@@ -107,15 +297,52 @@ pub fn handle(handler: Handler, stream: TcpStream) {
     let _ = tokio::spawn(task);
 }
 
+/// Spawn a task onto the event loop to handle an HTTP/2 connection.
+fn handle_h2<S>(handler: Handler, stream: S)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let stderr = handler.stderr.clone();
+    let connection = handler.connection();
+    let task = http2::serve_connection(handler, stream).then(move |res| {
+        drop(connection);
+        res.map_err(|e| try_error!(stderr, "failed to process the HTTP/2 connection: {}", e))
+    });
+
+    let _ = tokio::spawn(task);
+}
+
+/// Match the request and generate the response.  This is shared by HTTP/1.1 and HTTP/2.
 #[allow(box_pointers)]
-fn respond(handler: Handler, request: &Request<()>) -> FutResponse {
-    let matcher = Matcher::new(
-        handler.enabled,
-        handler.stdout.clone(),
-        handler.stderr.clone(),
-    );
+crate fn respond(handler: Handler, request: Request<Bytes>) -> FutResponse {
+    if request.extensions().get::<codec::BodyTooLarge>().is_some() {
+        try_error!(
+            handler.stderr,
+            "Request body is over the maximum of {} bytes",
+            handler.max_body_size
+        );
+        return util::error_response_fut(
+            "Request body too large".to_string(),
+            StatusCode::PAYLOAD_TOO_LARGE,
+        );
+    }
+
+    // The matchers only look at the request head, so split the body off here and hand it to
+    // the response generation separately.
+    let (parts, body) = request.into_parts();
+    let request = Request::from_parts(parts, ());
+
+    let matcher = handler.matcher.clone();
 
-    if let Ok(mapping) = matcher.get_match(&request, &handler.static_mappings) {
+    let static_match = {
+        let static_mappings = match handler.static_mappings.read() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        matcher.get_match(&request, &static_mappings)
+    };
+
+    if let Ok(mapping) = static_match {
         try_trace!(handler.stdout, "");
         try_trace!(
             handler.stdout,
@@ -123,7 +350,8 @@ fn respond(handler: Handler, request: &Request<()>) -> FutResponse {
             format!(" Matched '{}' ", mapping.name()),
             80
         );
-        http_response(handler, &request, mapping.response())
+        handler.hits.hit(&mapping);
+        http_response(handler, &request, body, mapping.response())
     } else {
         let dynamic_mappings = handler.dynamic_mappings.clone();
         let locked_dynamic_mappings = match dynamic_mappings.lock() {
@@ -139,95 +367,159 @@ fn respond(handler: Handler, request: &Request<()>) -> FutResponse {
                 format!(" Matched '{}' ", mapping.name()),
                 80
             );
-            http_response(handler, &request, mapping.response())
+            handler.hits.hit(&mapping);
+            http_response(handler, &request, body, mapping.response())
+        } else if let Some(fallback) = handler.fallback.clone() {
+            try_trace!(handler.stdout, "No mapping found, using the fallback proxy");
+            http_response(handler, &request, body, &fallback)
         } else {
             try_error!(handler.stderr, "No mapping found");
-            util::error_response_fut("No mapping found".to_string(), StatusCode::NOT_FOUND)
+            let near_misses = near_misses(&handler, &matcher, &request, &locked_dynamic_mappings);
+            for near_miss in &near_misses {
+                try_error!(handler.stderr, "Near miss: {}", near_miss);
+            }
+
+            if handler.diagnostics {
+                util::near_miss_response_fut(
+                    "No mapping found".to_string(),
+                    near_misses,
+                    StatusCode::NOT_FOUND,
+                )
+            } else {
+                util::error_response_fut("No mapping found".to_string(), StatusCode::NOT_FOUND)
+            }
         }
     }
 }
 
+/// Find the closest near misses across the static and dynamic mappings.
+fn near_misses(
+    handler: &Handler,
+    matcher: &Matcher,
+    request: &Request<()>,
+    dynamic_mappings: &config::Mappings,
+) -> Vec<NearMiss> {
+    let mut near_misses = {
+        let static_mappings = match handler.static_mappings.read() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        matcher.near_misses(request, &static_mappings, NEAR_MISS_LIMIT)
+    };
+    near_misses.extend(matcher.near_misses(request, dynamic_mappings, NEAR_MISS_LIMIT));
+    near_misses.sort_by(NearMiss::closeness);
+    near_misses.truncate(NEAR_MISS_LIMIT);
+    near_misses
+}
+
 #[allow(box_pointers)]
 fn http_response(
     handler: Handler,
     request: &Request<()>,
+    body: Bytes,
     response_config: &config::Response,
 ) -> FutResponse {
     if let Some(proxy_base_url) = response_config.proxy_base_url() {
-        let full_url = format!("{}{}", proxy_base_url, request.uri());
-        let (tx, rx) = futures::sync::mpsc::unbounded();
-        let headers = response_config.additional_proxy_request_headers().clone();
+        let upstream = UpstreamRequest::new(
+            request,
+            body,
+            proxy_base_url,
+            response_config.additional_proxy_request_headers(),
+            &handler.stderr,
+        );
+        let recording = handler
+            .recorder
+            .as_ref()
+            .map(|recorder| (recorder.clone(), recorder.recording(request)));
+        let response_stdout = handler.stdout.clone();
+        let response_stderr = handler.stderr.clone();
+        let upstream_config = handler.upstream_config.for_response(response_config);
+        let (tx, rx) = futures::sync::oneshot::channel();
         let proxy_config = handler.proxy_config.clone();
+        let proxy_url = upstream.url.parse::<Uri>().ok().and_then(|uri| {
+            proxy_config
+                .proxy_url_for(&uri, *response_config.use_proxy())
+                .map(str::to_string)
+        });
+        let client_tls = proxy_config.client_tls_for(&upstream.url).cloned();
+        let clients = handler.clients.clone();
         tokio::spawn_async(async move {
-            if *proxy_config.use_proxy() {
-                if let Some(url_str) = proxy_config.proxy_url() {
-                    let proxy_uri = url_str.parse().expect("Unable to parse proxy URI");
-                    let mut proxy = Proxy::new(Intercept::All, proxy_uri);
-                    if let Some(username) = proxy_config.proxy_username() {
-                        if let Some(password) = proxy_config.proxy_password() {
-                            if let Ok(creds) = Credentials::basic(username, password) {
-                                proxy.set_authorization(creds);
-                            }
-                        }
-                    }
-
-                    let connector = HttpConnector::new(4);
-                    let proxy_connector = ProxyConnector::from_proxy(connector, proxy)
-                        .expect("Unable to create proxy connector!");
-                    let client = Client::builder()
-                        .set_host(true)
-                        .build::<_, hyper::Body>(proxy_connector);
-                    await!(run_request(
+            let stdout = handler.stdout;
+            let stderr = handler.stderr;
+            let result = if let Some(proxy_url) = proxy_url {
+                match clients.proxy(
+                    &proxy_url,
+                    &proxy_config,
+                    &upstream_config,
+                    client_tls.as_ref(),
+                ) {
+                    Ok(client) => await!(run_request(
                         client,
-                        tx,
-                        full_url,
-                        handler.stdout.clone(),
-                        handler.stderr.clone(),
-                        headers
-                    ));
-                } else {
-                    panic!("Unable to determine proxy url!");
+                        upstream,
+                        upstream_config,
+                        stdout.clone(),
+                        stderr.clone()
+                    )),
+                    Err(e) => error_result(&e, &stderr),
+                }
+            } else if upstream.url.starts_with("https") {
+                match clients.https(&upstream_config, client_tls.as_ref()) {
+                    Ok(client) => await!(run_request(
+                        client,
+                        upstream,
+                        upstream_config,
+                        stdout.clone(),
+                        stderr.clone()
+                    )),
+                    Err(e) => error_result(&e, &stderr),
                 }
-            } else if full_url.starts_with("https") {
-                let https_connector = HttpsConnector::new(4).expect("TLS initialization failed");
-                let client = Client::builder()
-                    .set_host(true)
-                    .build::<_, hyper::Body>(https_connector);
-                await!(run_request(
-                    client,
-                    tx,
-                    full_url,
-                    handler.stdout.clone(),
-                    handler.stderr.clone(),
-                    headers
-                ));
             } else {
-                let http_connector = HttpConnector::new(4);
-                let client = Client::builder()
-                    .set_host(true)
-                    .build::<_, hyper::Body>(http_connector);
-                await!(run_request(
-                    client,
-                    tx,
-                    full_url,
-                    handler.stdout,
-                    handler.stderr,
-                    headers
-                ));
+                match clients.http(&upstream_config) {
+                    Ok(client) => await!(run_request(
+                        client,
+                        upstream,
+                        upstream_config,
+                        stdout.clone(),
+                        stderr.clone()
+                    )),
+                    Err(e) => error_result(&e, &stderr),
+                }
+            };
+
+            if tx.send(result).is_err() {
+                try_error!(
+                    stderr,
+                    "Unable to send upstream response, the client has gone away"
+                );
             }
         });
 
-        Box::new(
-            rx.fold(String::new(), |mut buffer, res| {
-                match res {
-                    Ok(val) => buffer.push_str(&val),
-                    Err(e) => buffer.push_str(&e.to_string()),
+        Box::new(rx.then(move |upstream_result| match upstream_result {
+            Ok(Ok(response)) => {
+                if let Some((recorder, recording)) = recording {
+                    Either::A(recorder.record(recording, &response).then(move |recorded| {
+                        match recorded {
+                            Ok(Some(path)) => {
+                                try_info!(response_stdout, "Recorded '{}'", path.display())
+                            }
+                            Ok(None) => try_trace!(response_stdout, "Skipped duplicate recording"),
+                            Err(e) => try_error!(response_stderr, "Unable to record: {}", e),
+                        }
+                        Ok::<_, String>(response)
+                    }))
+                } else {
+                    Either::B(future::ok::<_, String>(response))
                 }
-                futures::future::ok(buffer)
-            })
-            .map_err(|_| "Error processing upstream response".to_string())
-            .map(Response::new),
-        )
+            }
+            Ok(Err(response)) => Either::B(future::ok(response)),
+            Err(_) => {
+                try_error!(response_stderr, "{}", UpstreamCanceled);
+                Either::B(future::ok(util::error_response(
+                    UpstreamCanceled.to_string(),
+                    UpstreamCanceled.status_code(),
+                )))
+            }
+        }))
     } else {
         let mut response_builder = Response::builder();
         for header in response_config.headers() {
@@ -244,7 +536,9 @@ fn http_response(
             let _ = response_builder.status(StatusCode::OK);
         }
 
-        let body = if let Some(body_file_name) = response_config.body_file_name() {
+        let body = if let Some(body) = response_config.body() {
+            body.clone()
+        } else if let Some(body_file_name) = response_config.body_file_name() {
             match load(handler.files_path, body_file_name) {
                 Ok(body) => body,
                 Err(e) => e.to_string(),
@@ -260,58 +554,205 @@ fn http_response(
     }
 }
 
+/// The request to send upstream when proxying.
+#[derive(Clone, Debug)]
+struct UpstreamRequest {
+    /// The method of the original request.
+    method: Method,
+    /// The full upstream url, i.e. the proxy base url plus the original path and query.
+    url: String,
+    /// The end-to-end headers of the original request, plus any additional proxy headers.
+    headers: HeaderMap,
+    /// The body of the original request.
+    body: Bytes,
+}
+
+impl UpstreamRequest {
+    fn new(
+        request: &Request<()>,
+        body: Bytes,
+        proxy_base_url: &str,
+        additional_headers: &[config::Header],
+        stderr: &Option<Logger>,
+    ) -> Self {
+        // `Host` is set by the client from the upstream url, and `Content-Length` from the body.
+        // `Accept-Encoding` is dropped, as the response body is passed back as text.
+        let mut headers = header::forwardable_headers(
+            request.headers(),
+            &[HOST, CONTENT_LENGTH, ACCEPT_ENCODING],
+        );
+
+        // Additional proxy headers override any incoming headers with the same name.
+        for additional in additional_headers {
+            match (
+                HeaderName::from_bytes(additional.key().as_bytes()),
+                HeaderValue::from_str(additional.value()),
+            ) {
+                (Ok(name), Ok(value)) => {
+                    let _ = headers.insert(name, value);
+                }
+                _ => try_error!(
+                    stderr,
+                    "Invalid additional proxy request header: {}",
+                    additional
+                ),
+            }
+        }
+
+        Self {
+            method: request.method().clone(),
+            url: format!("{}{}", proxy_base_url, path_and_query(request)),
+            headers,
+            body,
+        }
+    }
+
+    fn to_hyper(&self) -> Result<HyperRequest<hyper::Body>, http::Error> {
+        let mut request_builder = HyperRequest::builder();
+        let _ = request_builder
+            .method(self.method.clone())
+            .uri(self.url.as_str());
+        let mut request = request_builder.body(hyper::Body::from(self.body.clone()))?;
+        *request.headers_mut() = self.headers.clone();
+        Ok(request)
+    }
+}
+
+/// The path and query of the request.  HTTP/2 requests carry an absolute-form uri, so the
+/// scheme and authority are dropped.
+crate fn path_and_query(request: &Request<()>) -> &str {
+    request.uri().path_and_query().map_or_else(
+        || request.uri().path(),
+        |path_and_query| path_and_query.as_str(),
+    )
+}
+
+/// The result of an upstream request.
+///
+/// `Ok` holds the upstream response, `Err` holds the error response generated when the upstream
+/// request failed.
+type UpstreamResult = Result<Response<String>, Response<String>>;
+
+/// Log the given error and generate the error response for it.
+fn error_result(e: &Error, stderr: &Option<Logger>) -> UpstreamResult {
+    try_error!(stderr, "{}", e);
+    Err(util::error_response(e.to_string(), e.status_code()))
+}
+
+/// Send the upstream request, retrying failed attempts with backoff.
+///
+/// The timeout covers each whole attempt, i.e. both the response head and reading the body.
+/// Only idempotent methods are retried, unless the upstream configuration says otherwise.
 async fn run_request<C>(
     client: Client<C, hyper::Body>,
-    tx: futures::sync::mpsc::UnboundedSender<Result<String, String>>,
-    url: String,
+    upstream: UpstreamRequest,
+    upstream_config: config::Upstream,
     stdout: Option<Logger>,
     stderr: Option<Logger>,
-    headers: Vec<config::Header>,
-) where
+) -> UpstreamResult
+where
     C: hyper::client::connect::Connect + Sync + 'static,
 {
-    match await!({
-        try_trace!(stdout, "Making request to {}", url);
-        let mut request_builder = HyperRequest::get(url);
+    let retries = upstream_config.retries_for(&upstream.method);
+    let mut retry = 0;
+    let result = loop {
+        try_trace!(
+            stdout,
+            "Making {} request to {}",
+            upstream.method,
+            upstream.url
+        );
+        let request = match upstream.to_hyper() {
+            Ok(request) => request,
+            Err(e) => return error_result(&Error::UpstreamRequest(e), &stderr),
+        };
+        let attempt = client.request(request).and_then(|response| {
+            let (parts, body) = response.into_parts();
+            body.concat2().map(move |body| (parts, body))
+        });
 
-        for header in headers {
-            let _ = request_builder.header(&header.key()[..], &header.value()[..]);
-        }
+        match await!(attempt.timeout(upstream_config.timeout())) {
+            Ok(response) => break Ok(response),
+            Err(e) => {
+                if retry >= retries {
+                    break Err(e);
+                }
 
-        let body = request_builder
-            .body(hyper::Body::empty())
-            .expect("Unable to create upstream request");
-        client
-            .request(body)
-            .timeout(std::time::Duration::from_secs(10))
-    }) {
-        Ok(response) => {
-            let body = await!({
-                response
-                    .into_body()
-                    .map_err(|_| ())
-                    .fold(Vec::new(), |mut v, chunk| {
-                        v.extend_from_slice(&chunk);
-                        futures::future::ok(v)
-                    })
-            });
+                let backoff = upstream_config.backoff(retry);
+                try_trace!(
+                    stdout,
+                    "Upstream request failed: {}, retrying in {}ms",
+                    e,
+                    backoff.as_millis()
+                );
+                let _ = await!(Delay::new(Instant::now() + backoff));
+                retry += 1;
+            }
+        }
+    };
 
-            if let Ok(body) = body {
-                let body_str = String::from_utf8_lossy(&body).into_owned();
-                tx.unbounded_send(Ok(body_str))
-                    .expect("Unable to send upstream response!");
-            } else {
-                try_error!(stderr, "Unable to process upstream response!");
-                tx.unbounded_send(Err("Unable to process upstream response!".to_string()))
-                    .expect("Unable to send upstream response!");
+    match result {
+        Ok((mut parts, body)) => {
+            // The codec writes its own framing, `Date` and `Server` headers.
+            parts.headers =
+                header::forwardable_headers(&parts.headers, &[CONTENT_LENGTH, DATE, SERVER]);
+            // The response body is passed back, and recorded, as text, so a binary or encoded
+            // body is an error rather than silently mangled.
+            match String::from_utf8(body.to_vec()) {
+                Ok(body) => Ok(Response::from_parts(parts, body)),
+                Err(e) => error_result(&Error::UpstreamBody(e), &stderr),
             }
         }
         Err(e) => {
-            try_error!(stderr, "Unable to process upstream response! {}", e);
-            tx.unbounded_send(Err(format!("Unable to process upstream response! {}", e)))
-                .expect("Unable to send upstream response!");
+            let attempts = retry + 1;
+            try_error!(
+                stderr,
+                "Unable to process upstream response after {} attempt(s)! {}",
+                attempts,
+                e
+            );
+            let status_code = if is_timeout(&e) {
+                StatusCode::GATEWAY_TIMEOUT
+            } else {
+                StatusCode::BAD_GATEWAY
+            };
+            Err(util::error_response(
+                format!(
+                    "Unable to process upstream response after {} attempt(s)! {}",
+                    attempts, e
+                ),
+                status_code,
+            ))
+        }
+    }
+}
+
+/// Did the upstream request fail because the total or the connect timeout elapsed?
+fn is_timeout(e: &TimeoutError<hyper::Error>) -> bool {
+    if e.is_elapsed() {
+        return true;
+    }
+
+    let mut source = e.get_ref().map(|e| e as &(dyn std::error::Error + 'static));
+    while let Some(err) = source {
+        if let Some(io_err) = err.downcast_ref::<io::Error>() {
+            if io_err.kind() == ErrorKind::TimedOut {
+                return true;
+            }
         }
+        source = err.source();
     }
+    false
+}
+
+/// Evict the given response file from the response file cache, so it is read again on the
+/// next request.
+crate fn evict_response(filename: &str) {
+    let mut cache = match STATIC_RESPONSE.lock() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    let _ = cache.cache_remove(&filename.to_string());
 }
 
 cached_key_result! {
@@ -342,26 +783,309 @@ cached_key_result! {
 }
 
 /// Start the async runtime handling.
+///
+/// The static mappings are validated first, and any problems fail startup.
 pub fn run(socket_addr: &SocketAddr, handler: Handler) -> Result<(), Error> {
+    validate_mappings(&handler)?;
     let listener = TcpListener::bind(&socket_addr)?;
 
     // Run the server.
     // try_trace!(handler.stdout, "{:?}", current);
     try_info!(handler.stdout, "Listening on '{}'", socket_addr);
+    let _watch = start_watch(&handler)?;
 
-    let map_stderr = handler.stderr.clone();
-    let process_stdout = handler.stdout.clone();
-
-    tokio::run({
-        listener
-            .incoming()
-            .map_err(move |e| try_error!(map_stderr, "Failed to accept socket: {}", e))
-            .for_each(move |socket| {
-                header::socket_info(&socket, &process_stdout);
-                handle(handler.clone(), socket);
-                Ok(())
-            })
-    });
+    tokio::run(serve(listener, handler));
 
     Ok(())
 }
+
+/// Start the async runtime handling from the given runtime configuration.
+///
+/// This loads the mappings, builds the `Handler`, and starts the plain listener, plus the TLS
+/// listener if it is enabled.  Recorded mappings and files are written to the runtime
+/// `mappings` and `files` directories.
+pub fn run_config(runtime: &config::Runtime, loggers: &Loggers) -> Result<(), Error> {
+    runtime.check()?;
+    let (stdout, stderr) = loggers.split();
+    let mappings_path = runtime.mappings_dir();
+    let mappings = config::Mappings::load(&mappings_path, &stderr)?;
+    let watch_path = if *runtime.watch() {
+        Some(mappings_path)
+    } else {
+        None
+    };
+
+    let mut record = runtime.record().clone();
+    let _ = record
+        .set_mappings_path(runtime.mappings_dir())
+        .set_files_path(runtime.files_dir());
+
+    let handler = Handler::new(
+        Enabled::all(),
+        mappings,
+        runtime.proxy().clone(),
+        runtime.files_dir(),
+    )
+    .stdout(stdout)
+    .stderr(stderr)
+    .record(record)
+    .upstream(*runtime.upstream())
+    .fallback(runtime.fallback().clone())
+    .watch(watch_path)
+    .diagnostics(*runtime.diagnostics())
+    .max_body_size(
+        runtime
+            .max_body_size()
+            .unwrap_or(codec::DEFAULT_MAX_BODY_SIZE),
+    );
+
+    let socket_addr = runtime.socket_addr()?;
+    if *runtime.tls().enabled() {
+        run_tls(
+            Some(&socket_addr),
+            &runtime.tls_socket_addr()?,
+            runtime.tls(),
+            handler,
+        )
+    } else {
+        run(&socket_addr, handler)
+    }
+}
+
+/// Start the async runtime handling with a TLS listener.
+///
+/// The plain listener is optional.  If it is given, both listeners share the same handler.
+/// The static mappings are validated first, and any problems fail startup.
+pub fn run_tls(
+    socket_addr: Option<&SocketAddr>,
+    tls_socket_addr: &SocketAddr,
+    server_tls: &config::ServerTls,
+    handler: Handler,
+) -> Result<(), Error> {
+    validate_mappings(&handler)?;
+    let acceptor = TlsAcceptor::from(Arc::new(server_tls.server_config()?));
+    let tls_listener = TcpListener::bind(tls_socket_addr)?;
+    try_info!(handler.stdout, "Listening (TLS) on '{}'", tls_socket_addr);
+
+    let listener = if let Some(socket_addr) = socket_addr {
+        let listener = TcpListener::bind(socket_addr)?;
+        try_info!(handler.stdout, "Listening on '{}'", socket_addr);
+        Some(listener)
+    } else {
+        None
+    };
+    let _watch = start_watch(&handler)?;
+
+    tokio::run(future::lazy(move || {
+        if let Some(listener) = listener {
+            let _ = tokio::spawn(serve(listener, handler.clone()));
+        }
+        let _ = tokio::spawn(serve_tls(tls_listener, acceptor, handler));
+        Ok(())
+    }));
+
+    Ok(())
+}
+
+/// Validate the static mappings, so a bad mapping fails startup rather than never matching.
+/// Warnings are only logged.
+crate fn validate_mappings(handler: &Handler) -> Result<(), Error> {
+    let static_mappings = match handler.static_mappings.read() {
+        Ok(guard) => guard,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    static_mappings.validate(&handler.files_path, &handler.stderr)
+}
+
+/// Start watching for mapping and response file changes, if enabled.
+///
+/// The watch stops when the returned `reload::Watch` is dropped.
+crate fn start_watch(handler: &Handler) -> Result<Option<reload::Watch>, Error> {
+    if let Some(mappings_path) = &handler.watch_path {
+        let watch = reload::watch(handler, mappings_path.clone())?;
+        try_info!(
+            handler.stdout,
+            "Watching '{}' and '{}' for changes",
+            mappings_path.display(),
+            handler.files_path.display()
+        );
+        Ok(Some(watch))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Accept plain connections on the given listener.
+///
+/// Connections that open with the HTTP/2 preface (h2c with prior knowledge) are served as
+/// HTTP/2, and all others as HTTP/1.1.
+crate fn serve(listener: TcpListener, handler: Handler) -> impl Future<Item = (), Error = ()> {
+    let map_stderr = handler.stderr.clone();
+
+    listener
+        .incoming()
+        .map_err(move |e| try_error!(map_stderr, "Failed to accept socket: {}", e))
+        .for_each(move |socket| {
+            header::socket_info(&socket, &handler.stdout);
+            let handler = handler.clone();
+            let preface_stderr = handler.stderr.clone();
+            // Count the connection from the accept, the handle takes over the count.
+            let connection = handler.connection();
+            let task = Preface::new(socket)
+                .map(move |(socket, h2c)| {
+                    let _connection = connection;
+                    if h2c {
+                        handle_h2(handler, socket);
+                    } else {
+                        handle(handler, socket);
+                    }
+                })
+                .map_err(move |e| try_error!(preface_stderr, "Failed to read the preface: {}", e));
+            let _ = tokio::spawn(task);
+            Ok(())
+        })
+}
+
+/// Accept TLS connections on the given listener.
+///
+/// Each handshake runs as its own task, so a slow client doesn't hold up the listener.
+/// Connections that negotiate `h2` with ALPN are served as HTTP/2, and all others as HTTP/1.1.
+fn serve_tls(
+    listener: TcpListener,
+    acceptor: TlsAcceptor,
+    handler: Handler,
+) -> impl Future<Item = (), Error = ()> {
+    let map_stderr = handler.stderr.clone();
+
+    listener
+        .incoming()
+        .map_err(move |e| try_error!(map_stderr, "Failed to accept socket: {}", e))
+        .for_each(move |socket| {
+            header::socket_info(&socket, &handler.stdout);
+            let handler = handler.clone();
+            let handshake_stderr = handler.stderr.clone();
+            // Count the connection from the accept, the handle takes over the count.
+            let connection = handler.connection();
+            let task = acceptor
+                .accept(socket)
+                .map(move |stream| {
+                    let _connection = connection;
+                    if stream.get_ref().1.get_alpn_protocol() == Some(http2::ALPN_H2) {
+                        handle_h2(handler, stream);
+                    } else {
+                        handle(handler, stream);
+                    }
+                })
+                .map_err(move |e| try_error!(handshake_stderr, "TLS handshake failed: {}", e));
+            let _ = tokio::spawn(task);
+            Ok(())
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Handler, UpstreamRequest};
+    use crate::config::{self, Mapping, Request, Response, Times};
+    use crate::matcher::Enabled;
+    use crate::server::MockServer;
+    use bytes::Bytes;
+    use futures::{Future, Stream};
+    use http::StatusCode;
+    use hyper::Client;
+    use std::path::PathBuf;
+    use tokio::runtime::Runtime;
+
+    fn handler() -> Handler {
+        Handler::new(
+            Enabled::all(),
+            config::Mappings::default(),
+            config::Proxy::default(),
+            PathBuf::from("files"),
+        )
+    }
+
+    fn get(runtime: &mut Runtime, url: String) -> (StatusCode, String) {
+        let uri = url.parse().expect("invalid uri");
+        let response = runtime.block_on(Client::new().get(uri).and_then(|response| {
+            let status = response.status();
+            response
+                .into_body()
+                .concat2()
+                .map(move |body| (status, String::from_utf8_lossy(&body).into_owned()))
+        }));
+        response.expect("unable to send the request")
+    }
+
+    #[test]
+    fn upstream_url_from_absolute_form() {
+        // HTTP/2 requests carry the scheme and authority in the uri.
+        let request = http::Request::get("http://localhost:32276/users?page=1")
+            .body(())
+            .expect("Unable to build request!");
+        let upstream =
+            UpstreamRequest::new(&request, Bytes::new(), "http://upstream.com", &[], &None);
+        assert_eq!(upstream.url, "http://upstream.com/users?page=1");
+    }
+
+    #[test]
+    fn upstream_drops_accept_encoding() {
+        let request = http::Request::get("/users")
+            .header("Accept-Encoding", "gzip")
+            .header("Accept", "application/json")
+            .body(())
+            .expect("Unable to build request!");
+        let upstream =
+            UpstreamRequest::new(&request, Bytes::new(), "http://upstream.com", &[], &None);
+        assert!(upstream.headers.get("accept-encoding").is_none());
+        assert!(upstream.headers.get("accept").is_some());
+    }
+
+    #[test]
+    fn fallback() {
+        let upstream = MockServer::start(handler()).expect("unable to start upstream");
+        let unmatched = upstream.mock(
+            Mapping::builder()
+                .name("Upstream Orders")
+                .expect(Times::Exactly(1))
+                .when(Request::get("/orders"))
+                .respond(Response::with_status(200).with_body("upstream orders"))
+                .build(),
+        );
+        let matched = upstream.mock(
+            Mapping::builder()
+                .name("Upstream Users")
+                .expect(Times::Never)
+                .when(Request::get("/users"))
+                .respond(Response::with_status(200).with_body("upstream users"))
+                .build(),
+        );
+        assert!(unmatched.is_ok() && matched.is_ok());
+
+        let server = MockServer::start(handler().fallback(Some(upstream.url())))
+            .expect("unable to start server");
+        let mocked = server.mock(
+            Mapping::builder()
+                .name("Users")
+                .when(Request::get("/users"))
+                .respond(Response::with_status(200).with_body("users"))
+                .build(),
+        );
+        assert!(mocked.is_ok());
+
+        let mut runtime = Runtime::new().expect("unable to create runtime");
+        assert_eq!(
+            get(&mut runtime, format!("{}/orders", server.url())),
+            (StatusCode::OK, "upstream orders".to_string())
+        );
+        assert_eq!(
+            get(&mut runtime, format!("{}/users", server.url())),
+            (StatusCode::OK, "users".to_string())
+        );
+        if let Err(e) = upstream.verify() {
+            assert!(false, e.to_string());
+        }
+
+        // Close the client connections, so the shutdowns don't wait out the grace period.
+        let _ = runtime.shutdown_now().wait();
+    }
+}
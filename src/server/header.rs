@@ -7,6 +7,8 @@
 // modified, or distributed except according to those terms.
 
 //! `libdeadmock` utilities
+use http::header::{HeaderMap, HeaderName, CONNECTION, PROXY_AUTHENTICATE, PROXY_AUTHORIZATION};
+use http::header::{TE, TRAILER, TRANSFER_ENCODING, UPGRADE};
 use slog::trace;
 use slog::Logger;
 use slog_try::try_trace;
@@ -54,3 +56,73 @@ crate fn socket_info(socket: &TcpStream, stdout: &Option<Logger>) {
         "peer_addr" => peer_addr,
     );
 }
+
+/// Is the given header a hop-by-hop header, i.e. one that is only meaningful for a single
+/// transport-level connection and must not be forwarded by a proxy (RFC 7230, section 6.1).
+fn is_hop_by_hop(name: &HeaderName) -> bool {
+    name == CONNECTION
+        || name == PROXY_AUTHENTICATE
+        || name == PROXY_AUTHORIZATION
+        || name == TE
+        || name == TRAILER
+        || name == TRANSFER_ENCODING
+        || name == UPGRADE
+        || name.as_str() == "keep-alive"
+        || name.as_str() == "proxy-connection"
+}
+
+/// Copy the end-to-end headers from the given `HeaderMap`.
+///
+/// Hop-by-hop headers, any headers named in the `Connection` header, and any headers in `skip`
+/// are dropped.
+crate fn forwardable_headers(headers: &HeaderMap, skip: &[HeaderName]) -> HeaderMap {
+    let connection_tokens: Vec<String> = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|token| token.trim().to_lowercase())
+        .collect();
+
+    let mut forwarded = HeaderMap::new();
+    for (name, value) in headers {
+        if is_hop_by_hop(name)
+            || skip.contains(name)
+            || connection_tokens.iter().any(|token| token == name.as_str())
+        {
+            continue;
+        }
+        let _ = forwarded.append(name.clone(), value.clone());
+    }
+    forwarded
+}
+
+#[cfg(test)]
+mod test {
+    use super::forwardable_headers;
+    use http::header::{HeaderMap, HeaderValue, ACCEPT, CONNECTION, HOST, TRANSFER_ENCODING};
+
+    #[test]
+    fn hop_by_hop_headers_are_dropped() {
+        let mut headers = HeaderMap::new();
+        let _ = headers.insert(ACCEPT, HeaderValue::from_static("application/json"));
+        let _ = headers.insert(HOST, HeaderValue::from_static("localhost:32276"));
+        let _ = headers.insert(CONNECTION, HeaderValue::from_static("keep-alive, x-hop"));
+        let _ = headers.insert("keep-alive", HeaderValue::from_static("timeout=5"));
+        let _ = headers.insert("x-hop", HeaderValue::from_static("true"));
+        let _ = headers.insert(TRANSFER_ENCODING, HeaderValue::from_static("chunked"));
+        let _ = headers.append("x-multi", HeaderValue::from_static("a"));
+        let _ = headers.append("x-multi", HeaderValue::from_static("b"));
+
+        let forwarded = forwardable_headers(&headers, &[HOST]);
+        assert_eq!(forwarded.len(), 3);
+        assert_eq!(
+            forwarded.get(ACCEPT),
+            Some(&HeaderValue::from_static("application/json"))
+        );
+        assert_eq!(forwarded.get_all("x-multi").iter().count(), 2);
+        assert!(forwarded.get(HOST).is_none());
+        assert!(forwarded.get(CONNECTION).is_none());
+        assert!(forwarded.get("x-hop").is_none());
+    }
+}
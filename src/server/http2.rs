@@ -0,0 +1,120 @@
+// Copyright (c) 2018 libdeadmock developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! HTTP/2 connection handling.
+//!
+//! HTTP/2 is framed by `hyper`, and each request is bridged into the same matcher and response
+//! generation as the HTTP/1.1 codec.
+use crate::server::codec::BodyTooLarge;
+use crate::server::handler::{self, Handler};
+use futures::{try_ready, Async, Future, Poll, Stream};
+use http::{Request, Response};
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use hyper::Body;
+use std::io::{self, ErrorKind};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+/// The start of the HTTP/2 client connection preface (RFC 7540, section 3.5).
+///
+/// No HTTP/1.1 method starts with this, so it is enough to tell the protocols apart.
+const PREFACE_START: &[u8] = b"PRI ";
+
+/// The ALPN protocol id for HTTP/2 over TLS.
+crate const ALPN_H2: &[u8] = b"h2";
+
+/// Peek at the start of a plain connection to see whether it is HTTP/2 with prior knowledge
+/// (h2c), or HTTP/1.1.
+///
+/// Only a peek is done, so the bytes are still there for whichever protocol serves the
+/// connection.  A connection that closes, or whose first read is shorter than the preface
+/// start, is handed to HTTP/1.1.
+#[derive(Debug)]
+crate struct Preface {
+    socket: Option<TcpStream>,
+}
+
+impl Preface {
+    crate fn new(socket: TcpStream) -> Self {
+        Self {
+            socket: Some(socket),
+        }
+    }
+}
+
+impl Future for Preface {
+    type Item = (TcpStream, bool);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, io::Error> {
+        let mut buf = [0; 4];
+        let len = match self.socket.as_mut() {
+            Some(socket) => try_ready!(socket.poll_peek(&mut buf)),
+            None => {
+                return Err(io::Error::new(
+                    ErrorKind::Other,
+                    "the preface was already checked",
+                ))
+            }
+        };
+
+        match self.socket.take() {
+            Some(socket) => Ok(Async::Ready((socket, &buf[..len] == PREFACE_START))),
+            None => Err(io::Error::new(
+                ErrorKind::Other,
+                "the preface was already checked",
+            )),
+        }
+    }
+}
+
+/// Serve an HTTP/2 connection, bridging each request into the `Handler`.
+///
+/// A body over the handler's maximum size is read but not kept, and the request is answered
+/// with a 413.
+#[allow(box_pointers)]
+crate fn serve_connection<S>(
+    handler: Handler,
+    stream: S,
+) -> impl Future<Item = (), Error = hyper::Error>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let service = service_fn(move |request: Request<Body>| {
+        let handler = handler.clone();
+        let max_body_size = handler.body_limit();
+        let (parts, body) = request.into_parts();
+
+        body.fold((Vec::new(), false), move |(mut buf, too_large), chunk| {
+            let too_large = too_large || buf.len() + chunk.len() > max_body_size;
+            if too_large {
+                buf.clear();
+            } else {
+                buf.extend_from_slice(&chunk);
+            }
+            Ok::<_, hyper::Error>((buf, too_large))
+        })
+        .map_err(|e| e.to_string())
+        .and_then(move |(body, too_large)| {
+            let mut request = Request::from_parts(parts, body.into());
+            if too_large {
+                let _ = request.extensions_mut().insert(BodyTooLarge);
+            }
+            handler::respond(handler, request)
+        })
+        .map(|response: Response<String>| {
+            let (parts, body) = response.into_parts();
+            Response::from_parts(parts, Body::from(body))
+        })
+    });
+
+    Http::new()
+        .http2_only(true)
+        .serve_connection(stream, service)
+}
@@ -0,0 +1,306 @@
+// Copyright (c) 2018 libdeadmock developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! An embeddable mock server, i.e. for integration tests.
+use crate::config::Mapping;
+use crate::error::Error;
+use crate::server::handler::{self, Handler};
+use crate::server::reload::Watch;
+use futures::sync::oneshot;
+use futures::{Future, Stream};
+use std::net::SocketAddr;
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::net::TcpListener;
+use tokio::runtime::Runtime;
+use tokio::timer::{Delay, Interval};
+use uuid::Uuid;
+
+/// How long a shutdown waits for in-flight connections to finish.
+const GRACE_PERIOD: Duration = Duration::from_secs(5);
+/// How often a shutdown checks whether the in-flight connections have finished.
+const IDLE_POLL: Duration = Duration::from_millis(10);
+
+/// A mock server bound to a local address.
+///
+/// The server stops accepting connections, and stops watching for changes, when it is shut down
+/// or dropped.  If it owns its runtime, the in-flight connections are then given a grace period
+/// to finish, before the runtime is shut down.
+///
+/// # Example
+///
+/// ```no_run
+/// # use libdeadmock::config;
+/// # use libdeadmock::matcher::Enabled;
+/// # use libdeadmock::server::{Handler, MockServer};
+/// # use std::path::PathBuf;
+/// #
+/// # fn main() {
+///     let handler = Handler::new(
+///         Enabled::all(),
+///         config::Mappings::default(),
+///         config::Proxy::default(),
+///         PathBuf::from("files"),
+///     );
+///     let server = MockServer::start(handler).unwrap();
+///     println!("Mocking on {}", server.url());
+///     server.shutdown();
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct MockServer {
+    addr: SocketAddr,
+    handler: Handler,
+    shutdown: Option<oneshot::Sender<()>>,
+    runtime: Option<Runtime>,
+    watch: Option<Watch>,
+    verify_on_drop: bool,
+}
+
+impl MockServer {
+    /// Start a mock server on an ephemeral port on `127.0.0.1`, on its own background runtime.
+    pub fn start(handler: Handler) -> Result<Self, Error> {
+        let (mut server, serve) = Self::bind(&SocketAddr::from(([127, 0, 0, 1], 0)), handler)?;
+        let mut runtime = Runtime::new()?;
+        let _ = runtime.spawn(serve);
+        server.runtime = Some(runtime);
+        Ok(server)
+    }
+
+    /// Bind a mock server to the given address, for the caller to run on their own runtime.
+    ///
+    /// The returned future accepts connections until the server is shut down or dropped.  Use
+    /// port `0` for an ephemeral port, and `addr` for the port that was bound.
+    pub fn bind(
+        addr: &SocketAddr,
+        handler: Handler,
+    ) -> Result<(Self, impl Future<Item = (), Error = ()> + Send), Error> {
+        handler::validate_mappings(&handler)?;
+        let listener = TcpListener::bind(addr)?;
+        let addr = listener.local_addr()?;
+        let watch = handler::start_watch(&handler)?;
+
+        let (tx, rx) = oneshot::channel();
+        let serve = handler::serve(listener, handler.clone())
+            .select(rx.map_err(|_| ()))
+            .map(|_| ())
+            .map_err(|_| ());
+
+        Ok((
+            Self {
+                addr,
+                handler,
+                shutdown: Some(tx),
+                runtime: None,
+                watch,
+                verify_on_drop: false,
+            },
+            serve,
+        ))
+    }
+
+    /// The address the server is bound to.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// The base url of the server, i.e. `http://127.0.0.1:54321`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Add a mapping to the running server, returning its id.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libdeadmock::config::{self, Mapping, Request, Response};
+    /// # use libdeadmock::matcher::Enabled;
+    /// # use libdeadmock::server::{Handler, MockServer};
+    /// # use std::path::PathBuf;
+    /// #
+    /// # fn main() {
+    /// #   let handler = Handler::new(
+    /// #       Enabled::all(),
+    /// #       config::Mappings::default(),
+    /// #       config::Proxy::default(),
+    /// #       PathBuf::from("files"),
+    /// #   );
+    ///     let server = MockServer::start(handler).unwrap();
+    ///     let id = server
+    ///         .mock(
+    ///             Mapping::builder()
+    ///                 .name("Get Users")
+    ///                 .when(Request::get("/users"))
+    ///                 .respond(Response::with_status(200).with_body("[]"))
+    ///                 .build(),
+    ///         )
+    ///         .unwrap();
+    /// # }
+    /// ```
+    pub fn mock(&self, mapping: Mapping) -> Result<Uuid, Error> {
+        self.handler.add_mapping(mapping)
+    }
+
+    /// Check the mapping expectations (see `Mapping::expect`) against the number of times each
+    /// mapping has matched so far.
+    ///
+    /// The error lists every unmet expectation.
+    pub fn verify(&self) -> Result<(), Error> {
+        self.handler.verify()
+    }
+
+    /// Verify the mapping expectations when the server is shut down or dropped, and panic if any
+    /// are unmet.  This is skipped if the thread is already panicking.
+    pub fn verify_on_drop(mut self, verify_on_drop: bool) -> Self {
+        self.verify_on_drop = verify_on_drop;
+        self
+    }
+
+    /// Shut the server down.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+
+        // Dropping the watch stops the watcher thread.
+        let _ = self.watch.take();
+
+        if let Some(mut runtime) = self.runtime.take() {
+            // Race the in-flight connections finishing against the grace period, as idle
+            // keep-alive connections would otherwise hold the runtime open.  Then shut the
+            // runtime down, closing whatever is left.  This runs on its own thread, as the
+            // server may be dropped from within another runtime.
+            let handler = self.handler.clone();
+            let shutdown = thread::spawn(move || {
+                let idle = Interval::new_interval(IDLE_POLL)
+                    .take_while(move |_| Ok(handler.open_connections() > 0))
+                    .for_each(|_| Ok(()));
+                let grace = Delay::new(Instant::now() + GRACE_PERIOD);
+                let _ = runtime.block_on(idle.select2(grace));
+                let _ = runtime.shutdown_now().wait();
+            });
+            let _ = shutdown.join();
+        }
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.stop();
+
+        if self.verify_on_drop && !thread::panicking() {
+            if let Err(e) = self.handler.verify() {
+                panic!("{}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::MockServer;
+    use crate::config::{self, Mapping, Request, Response, Times};
+    use crate::matcher::Enabled;
+    use crate::server::Handler;
+    use futures::{Future, Stream};
+    use http::StatusCode;
+    use hyper::Client;
+    use serde_json::json;
+    use std::path::PathBuf;
+    use tokio::runtime::Runtime;
+
+    fn handler() -> Handler {
+        Handler::new(
+            Enabled::all(),
+            config::Mappings::default(),
+            config::Proxy::default(),
+            PathBuf::from("files"),
+        )
+    }
+
+    #[test]
+    fn ephemeral_servers() {
+        let first = MockServer::start(handler());
+        let second = MockServer::start(handler());
+
+        match (first, second) {
+            (Ok(first), Ok(second)) => {
+                assert_ne!(first.addr().port(), 0);
+                assert_ne!(first.addr(), second.addr());
+
+                let mut runtime = Runtime::new().expect("unable to create runtime");
+                let uri = format!("{}/nothing", first.url())
+                    .parse()
+                    .expect("invalid uri");
+                let response = runtime.block_on(Client::new().get(uri).and_then(|response| {
+                    let status = response.status();
+                    response
+                        .into_body()
+                        .concat2()
+                        .map(move |body| (status, body))
+                }));
+
+                match response {
+                    Ok((status, body)) => {
+                        assert_eq!(status, StatusCode::NOT_FOUND);
+                        assert_eq!(&body[..], &br#"{"message":"No mapping found"}"#[..]);
+                    }
+                    Err(e) => assert!(false, e.to_string()),
+                }
+
+                let mocked = second.mock(
+                    Mapping::builder()
+                        .name("Get Users")
+                        .priority(1)
+                        .expect(Times::Exactly(2))
+                        .when(Request::get("/users"))
+                        .respond(Response::with_status(200).with_json_body(&json!([1, 2])))
+                        .build(),
+                );
+                assert!(mocked.is_ok());
+                let uri = format!("{}/users", second.url())
+                    .parse()
+                    .expect("invalid uri");
+                let response = runtime.block_on(Client::new().get(uri).and_then(|response| {
+                    let status = response.status();
+                    response
+                        .into_body()
+                        .concat2()
+                        .map(move |body| (status, body))
+                }));
+
+                match response {
+                    Ok((status, body)) => {
+                        assert_eq!(status, StatusCode::OK);
+                        assert_eq!(&body[..], &b"[1,2]"[..]);
+                    }
+                    Err(e) => assert!(false, e.to_string()),
+                }
+
+                match second.verify() {
+                    Ok(_) => assert!(false, "Expected an unmet expectation"),
+                    Err(e) => assert!(e
+                        .to_string()
+                        .contains("expected exactly 2 match(es), got 1")),
+                }
+
+                // Close the client connections, so the shutdown doesn't wait out the grace period.
+                let _ = runtime.shutdown_now().wait();
+                let addr = first.addr();
+                first.shutdown();
+                assert!(std::net::TcpStream::connect(addr).is_err());
+            }
+            _ => assert!(false, "Not expected to error!"),
+        }
+    }
+}
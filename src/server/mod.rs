@@ -7,8 +7,16 @@
 // modified, or distributed except according to those terms.
 
 //! Async runtime for the server.
+crate mod client;
 crate mod codec;
+crate mod expect;
 crate mod handler;
 crate mod header;
+crate mod http2;
+crate mod mock;
+crate mod record;
+crate mod reload;
 
-pub use self::handler::{handle, run, Handler};
+pub use self::expect::Unmet;
+pub use self::handler::{handle, run, run_config, run_tls, Handler};
+pub use self::mock::MockServer;
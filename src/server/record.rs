@@ -0,0 +1,319 @@
+// Copyright (c) 2018 libdeadmock developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Record proxied traffic as mappings.
+use crate::config::{self, Header, Mapping};
+use crate::error::Error;
+use crate::server::handler;
+use futures::future::{self, Either};
+use futures::Future;
+use http::header::CONTENT_TYPE;
+use http::{Request, Response};
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio_threadpool::blocking;
+use uuid::Uuid;
+
+/// The request half of a recording, captured before the request is proxied.
+#[derive(Clone, Debug)]
+crate struct Recording {
+    /// The name of the generated mapping.
+    name: String,
+    /// The request matching configuration of the generated mapping.
+    request: config::Request,
+}
+
+/// Writes proxied request/response pairs out as mapping and response body files.
+#[derive(Clone, Debug)]
+crate struct Recorder {
+    /// The record mode configuration.
+    config: config::Record,
+    /// The request configurations recorded so far, used for de-duplication.
+    recorded: Arc<Mutex<HashSet<config::Request>>>,
+}
+
+/// The files written for one recording.
+#[derive(Debug)]
+struct RecordedFiles {
+    mapping: Mapping,
+    mapping_path: PathBuf,
+    body: String,
+    body_path: PathBuf,
+}
+
+impl RecordedFiles {
+    /// Write the response body file, then the mapping file.  This blocks on the file system.
+    fn write(&self) -> Result<PathBuf, Error> {
+        if let Some(files_path) = self.body_path.parent() {
+            fs::create_dir_all(files_path)?;
+        }
+        fs::write(&self.body_path, &self.body)?;
+
+        // Go through `toml::Value` so the tables are ordered after the plain values.
+        let mapping_toml = toml::Value::try_from(&self.mapping)?.to_string();
+        if let Some(mappings_path) = self.mapping_path.parent() {
+            fs::create_dir_all(mappings_path)?;
+        }
+        fs::write(&self.mapping_path, mapping_toml)?;
+
+        Ok(self.mapping_path.clone())
+    }
+}
+
+impl Recorder {
+    crate fn new(config: config::Record) -> Self {
+        Self {
+            config,
+            recorded: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Capture the parts of the given request that recorded mappings match on.
+    crate fn recording(&self, request: &Request<()>) -> Recording {
+        let method = if *self.config.match_method() {
+            Some(request.method().as_str().to_string())
+        } else {
+            None
+        };
+        let url = if *self.config.match_url() {
+            Some(handler::path_and_query(request).to_string())
+        } else {
+            None
+        };
+        let headers = self
+            .config
+            .match_headers()
+            .iter()
+            .filter_map(|name| {
+                request
+                    .headers()
+                    .get(name.as_str())
+                    .and_then(|value| value.to_str().ok())
+                    .map(|value| Header::new(name.clone(), value))
+            })
+            .collect();
+
+        Recording {
+            name: format!(
+                "Recorded - {} {}",
+                request.method(),
+                handler::path_and_query(request)
+            ),
+            request: config::Request::recorded(method, url, headers),
+        }
+    }
+
+    /// Write the mapping and response body files for the given recording and upstream response.
+    ///
+    /// The files are written on the blocking pool, so this must run on the threadpool runtime.
+    /// Resolves to the path of the mapping file, or `None` if the recording was skipped as a
+    /// duplicate.
+    ///
+    /// With de-duplication on, the request is held in the recorded set while its files are
+    /// written, and released again if a write fails, so only recordings that were written skip
+    /// later duplicates.
+    crate fn record(
+        &self,
+        recording: Recording,
+        response: &Response<String>,
+    ) -> impl Future<Item = Option<PathBuf>, Error = Error> {
+        let dedupe = *self.config.dedupe();
+        if dedupe && !self.reserve(&recording.request) {
+            return Either::A(future::ok(None));
+        }
+
+        let request = recording.request.clone();
+        let recorder = self.clone();
+        let files = self.files(recording, response);
+
+        Either::B(
+            future::poll_fn(move || blocking(|| files.write()))
+                .map_err(Error::from)
+                .and_then(|written| written)
+                .then(move |result| {
+                    if dedupe && result.is_err() {
+                        recorder.release(&request);
+                    }
+                    result.map(Some)
+                }),
+        )
+    }
+
+    /// Add the given request to the recorded set, returning `false` if it was already there.
+    fn reserve(&self, request: &config::Request) -> bool {
+        let mut recorded = match self.recorded.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        recorded.insert(request.clone())
+    }
+
+    /// Remove the given request from the recorded set.
+    fn release(&self, request: &config::Request) {
+        let mut recorded = match self.recorded.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let _ = recorded.remove(request);
+    }
+
+    /// Build the mapping and body files for the given recording and upstream response.
+    fn files(&self, recording: Recording, response: &Response<String>) -> RecordedFiles {
+        let id = Uuid::new_v4();
+        let body_file_name = format!("recorded-{}.{}", id, extension(response));
+        let headers = response
+            .headers()
+            .iter()
+            .filter_map(|(key, value)| {
+                value
+                    .to_str()
+                    .ok()
+                    .map(|value| Header::new(key.as_str(), value))
+            })
+            .collect();
+        let mapping = Mapping::new(
+            recording.name,
+            1,
+            recording.request,
+            config::Response::recorded(response.status().as_u16(), headers, body_file_name.clone()),
+        );
+
+        RecordedFiles {
+            mapping,
+            mapping_path: self
+                .config
+                .mappings_path()
+                .join(format!("recorded-{}.toml", id)),
+            body: response.body().clone(),
+            body_path: self.config.files_path().join(&body_file_name),
+        }
+    }
+}
+
+/// Pick a response body file extension from the `Content-Type` of the response.
+fn extension(response: &Response<String>) -> &'static str {
+    match response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+    {
+        Some(content_type) if content_type.contains("json") => "json",
+        Some(content_type) if content_type.contains("xml") => "xml",
+        Some(content_type) if content_type.contains("html") => "html",
+        _ => "txt",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Recorder;
+    use crate::config::{self, Mapping};
+    use http::{Request, Response};
+    use std::env;
+    use std::fs;
+    use tokio::runtime::Runtime;
+    use uuid::Uuid;
+
+    fn record_config() -> config::Record {
+        let root = env::temp_dir().join(format!("deadmock-record-{}", Uuid::new_v4()));
+        let mut record_config = config::Record::default();
+        let _ = record_config
+            .set_enabled(true)
+            .set_match_method(true)
+            .set_match_url(true)
+            .set_match_headers(vec!["X-Correlation-Id".to_string()])
+            .set_dedupe(true)
+            .set_mappings_path(root.join("mappings"))
+            .set_files_path(root.join("files"));
+        record_config
+    }
+
+    #[test]
+    fn record_and_dedupe() {
+        let recorder = Recorder::new(record_config());
+        let mut request_builder = Request::builder();
+        let _ = request_builder
+            .method("POST")
+            .uri("/users?page=1")
+            .header("X-Correlation-Id", "12345")
+            .header("Accept", "application/json");
+        let request = request_builder.body(()).expect("Unable to build request!");
+
+        let mut response_builder = Response::builder();
+        let _ = response_builder
+            .status(201)
+            .header("Content-Type", "application/json");
+        let response = response_builder
+            .body(r#"{"id":1}"#.to_string())
+            .expect("Unable to build response!");
+
+        let mut runtime = Runtime::new().expect("Unable to create runtime!");
+        match runtime.block_on(recorder.record(recorder.recording(&request), &response)) {
+            Ok(Some(mapping_path)) => {
+                let mapping_toml = fs::read(&mapping_path).expect("Unable to read mapping!");
+                let mapping: Mapping =
+                    toml::from_slice(&mapping_toml).expect("Unable to parse mapping!");
+                assert_eq!(mapping.name(), "Recorded - POST /users?page=1");
+                assert_eq!(mapping.request().method(), &Some("POST".to_string()));
+                assert_eq!(mapping.request().url(), &Some("/users?page=1".to_string()));
+                assert_eq!(mapping.request().headers().len(), 1);
+                assert_eq!(mapping.response().status(), &Some(201));
+
+                let body_file_name = mapping
+                    .response()
+                    .body_file_name()
+                    .clone()
+                    .expect("Expected a body file!");
+                assert!(body_file_name.ends_with(".json"));
+                let body = fs::read_to_string(recorder.config.files_path().join(body_file_name))
+                    .expect("Unable to read body!");
+                assert_eq!(body, r#"{"id":1}"#);
+            }
+            Ok(None) => assert!(false, "Not expected to be a duplicate!"),
+            Err(e) => assert!(false, e.to_string()),
+        }
+
+        match runtime.block_on(recorder.record(recorder.recording(&request), &response)) {
+            Ok(None) => {}
+            Ok(Some(_)) => assert!(false, "Expected the duplicate to be skipped!"),
+            Err(e) => assert!(false, e.to_string()),
+        }
+    }
+
+    #[test]
+    fn failed_write_is_not_deduped() {
+        let record_config = record_config();
+        let mappings_path = record_config.mappings_path().clone();
+        let recorder = Recorder::new(record_config);
+        let mut request_builder = Request::builder();
+        let _ = request_builder.method("GET").uri("/users");
+        let request = request_builder.body(()).expect("Unable to build request!");
+        let response = Response::builder()
+            .body("[]".to_string())
+            .expect("Unable to build response!");
+
+        // A file where the mappings directory should be fails the mapping write.
+        fs::create_dir_all(mappings_path.parent().expect("Expected a parent!"))
+            .expect("Unable to create the record directory!");
+        fs::write(&mappings_path, "").expect("Unable to block the mappings directory!");
+
+        let mut runtime = Runtime::new().expect("Unable to create runtime!");
+        assert!(runtime
+            .block_on(recorder.record(recorder.recording(&request), &response))
+            .is_err());
+
+        fs::remove_file(&mappings_path).expect("Unable to unblock the mappings directory!");
+        match runtime.block_on(recorder.record(recorder.recording(&request), &response)) {
+            Ok(Some(_)) => {}
+            Ok(None) => assert!(false, "The failed recording should not be a duplicate!"),
+            Err(e) => assert!(false, e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,233 @@
+// Copyright (c) 2018 libdeadmock developers
+//
+// Licensed under the Apache License, Version 2.0
+// <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0> or the MIT
+// license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. All files in the project carrying such notice may not be copied,
+// modified, or distributed except according to those terms.
+
+//! Hot reload of the static mappings and response files.
+use crate::config;
+use crate::error::Error;
+use crate::server::handler::{self, Handler};
+use notify::{watcher, DebouncedEvent, RecursiveMode, Watcher};
+use slog::{error, info, trace, warn};
+use slog_try::{try_error, try_info, try_trace, try_warn};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// How long to wait for a burst of file system events to settle before reloading.
+const DEBOUNCE_MS: u64 = 250;
+/// How often the watcher thread checks whether it has been stopped.
+const STOP_POLL_MS: u64 = 100;
+
+/// A running watch.  The watcher thread stops when this is dropped.
+#[derive(Debug)]
+crate struct Watch {
+    stopped: Arc<AtomicBool>,
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Watch the mappings and files directories for changes.
+///
+/// Changed mappings are reloaded as a set and swapped in.  If the new set fails to load or to
+/// validate, the old set stays in place and the error is logged.  Changed response files are evicted from
+/// the response file cache.
+///
+/// The watcher runs on its own thread until the returned `Watch` is dropped.
+///
+/// The directories are canonicalized first, as the file system events carry absolute paths.
+/// A missing files directory is not watched, as it isn't required to serve mappings.
+crate fn watch(handler: &Handler, mappings_path: PathBuf) -> Result<Watch, Error> {
+    let mappings_path = mappings_path.canonicalize()?;
+    let files_path = handler.files_path().canonicalize().ok();
+    let (tx, rx) = channel();
+    let mut watcher = watcher(tx, Duration::from_millis(DEBOUNCE_MS))?;
+    watcher.watch(&mappings_path, RecursiveMode::Recursive)?;
+    if let Some(files_path) = &files_path {
+        watcher.watch(files_path, RecursiveMode::Recursive)?;
+    } else {
+        try_warn!(
+            handler.stderr_log(),
+            "Not watching '{}', it does not exist",
+            handler.files_path().display()
+        );
+    }
+
+    let handler = handler.clone();
+    let stopped = Arc::new(AtomicBool::new(false));
+    let thread_stopped = stopped.clone();
+    let _ = thread::spawn(move || {
+        // Keep the watcher alive as long as the thread.
+        let _watcher = watcher;
+
+        loop {
+            let event = match rx.recv_timeout(Duration::from_millis(STOP_POLL_MS)) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => {
+                    if thread_stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            let paths = match event {
+                DebouncedEvent::Create(path)
+                | DebouncedEvent::Write(path)
+                | DebouncedEvent::Remove(path) => vec![path],
+                DebouncedEvent::Rename(from, to) => vec![from, to],
+                DebouncedEvent::Error(e, _) => {
+                    try_error!(handler.stderr_log(), "Unable to watch for changes: {}", e);
+                    continue;
+                }
+                _ => continue,
+            };
+
+            if paths.iter().any(|path| path.starts_with(&mappings_path)) {
+                reload_mappings(&handler, &mappings_path);
+            }
+
+            if let Some(files_path) = &files_path {
+                for path in paths.iter().filter(|path| path.starts_with(files_path)) {
+                    evict_file(&handler, path);
+                }
+            }
+        }
+    });
+
+    Ok(Watch { stopped })
+}
+
+fn reload_mappings(handler: &Handler, mappings_path: &Path) {
+    let reloaded =
+        config::Mappings::load(mappings_path, handler.stderr_log()).and_then(|mappings| {
+            mappings.validate(handler.files_path(), handler.stderr_log())?;
+            Ok(mappings)
+        });
+
+    match reloaded {
+        Ok(mappings) => {
+            try_info!(
+                handler.stdout_log(),
+                "Reloaded {} mapping(s) from '{}'",
+                mappings.inner().len(),
+                mappings_path.display()
+            );
+            handler.swap_static_mappings(mappings);
+        }
+        Err(e) => try_error!(
+            handler.stderr_log(),
+            "Unable to reload the mappings, keeping the current set: {}",
+            e
+        ),
+    }
+}
+
+fn evict_file(handler: &Handler, path: &Path) {
+    if let Some(file_name) = path.file_name() {
+        let file_name = file_name.to_string_lossy();
+        try_trace!(
+            handler.stdout_log(),
+            "Evicting '{}' from the response cache",
+            file_name
+        );
+        handler::evict_response(&file_name);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::watch;
+    use crate::config;
+    use crate::matcher::Enabled;
+    use crate::server::handler::{self, Handler};
+    use bytes::Bytes;
+    use futures::Future;
+    use http::{Request, StatusCode};
+    use std::env;
+    use std::fs;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use uuid::Uuid;
+
+    const MAPPING_TOML: &str = r#"name = "Reloaded"
+priority = 1
+
+[request]
+method = "GET"
+url = "/reloaded"
+
+[response]
+status = 200
+body = "reloaded"
+"#;
+
+    fn status(handler: &Handler) -> StatusCode {
+        let request = Request::get("/reloaded")
+            .body(Bytes::new())
+            .expect("Unable to build request!");
+        handler::respond(handler.clone(), request)
+            .wait()
+            .expect("Unable to respond!")
+            .status()
+    }
+
+    #[test]
+    fn reload_changed_mappings() {
+        let root = env::temp_dir().join(format!("deadmock-reload-{}", Uuid::new_v4()));
+        let mappings_path = root.join("mappings");
+        let files_path = root.join("files");
+        fs::create_dir_all(&mappings_path).expect("Unable to create the mappings directory!");
+        fs::create_dir_all(&files_path).expect("Unable to create the files directory!");
+
+        // Watch through a path that isn't canonical, as the event paths are.
+        let handler = Handler::new(
+            Enabled::all(),
+            config::Mappings::default(),
+            config::Proxy::default(),
+            files_path.join("..").join("files"),
+        );
+        let _watch = match watch(&handler, files_path.join("..").join("mappings")) {
+            Ok(watch) => watch,
+            Err(e) => return assert!(false, e.to_string()),
+        };
+        assert_eq!(status(&handler), StatusCode::NOT_FOUND);
+
+        fs::write(mappings_path.join("reloaded.toml"), MAPPING_TOML)
+            .expect("Unable to write the mapping!");
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while status(&handler) != StatusCode::OK {
+            if Instant::now() > deadline {
+                return assert!(false, "The changed mapping was not reloaded!");
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    #[test]
+    fn missing_files_directory() {
+        let root = env::temp_dir().join(format!("deadmock-reload-{}", Uuid::new_v4()));
+        let mappings_path = root.join("mappings");
+        fs::create_dir_all(&mappings_path).expect("Unable to create the mappings directory!");
+
+        let handler = Handler::new(
+            Enabled::all(),
+            config::Mappings::default(),
+            config::Proxy::default(),
+            root.join("files"),
+        );
+        assert!(watch(&handler, mappings_path).is_ok());
+        let _ = fs::remove_dir_all(&root);
+    }
+}
@@ -8,6 +8,7 @@
 
 //! `libdeadmock` utilities
 use crate::error::Error;
+use crate::matcher::NearMiss;
 use futures::{future, Future};
 use http::header::{HeaderValue, CONTENT_TYPE};
 use http::{Response, StatusCode};
@@ -41,12 +42,36 @@ crate fn error_response_fut(body: String, status_code: StatusCode) -> FutRespons
 }
 
 crate fn error_response(message: String, status_code: StatusCode) -> Response<String> {
+    near_miss_response(message, vec![], status_code)
+}
+
+#[allow(box_pointers)]
+crate fn near_miss_response_fut(
+    message: String,
+    near_misses: Vec<NearMiss>,
+    status_code: StatusCode,
+) -> FutResponse {
+    Box::new(future::ok(near_miss_response(
+        message,
+        near_misses,
+        status_code,
+    )))
+}
+
+crate fn near_miss_response(
+    message: String,
+    near_misses: Vec<NearMiss>,
+    status_code: StatusCode,
+) -> Response<String> {
     let mut response = Response::builder();
     let _ = response
         .header(CONTENT_TYPE, HeaderValue::from_static("application/json"))
         .status(status_code);
 
-    if let Ok(message) = serde_json::to_string(&ErrorMessage { message }) {
+    if let Ok(message) = serde_json::to_string(&ErrorMessage {
+        message,
+        near_misses,
+    }) {
         if let Ok(response) = response.body(message) {
             return response;
         }
@@ -58,4 +83,6 @@ crate fn error_response(message: String, status_code: StatusCode) -> Response<St
 #[derive(Serialize)]
 struct ErrorMessage {
     message: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    near_misses: Vec<NearMiss>,
 }